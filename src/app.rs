@@ -1,32 +1,107 @@
-use std::{fs::read_dir, iter, path::Path, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    iter, mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, Utc};
 use iced::{
-    theme,
-    widget::{button, column, container, image as iced_image, scrollable, text, Column, Space},
-    window, Alignment, Application, Command, Element, Length, Subscription,
+    event, keyboard, subscription, theme,
+    widget::{button, column, container, image as iced_image, row, text, Column, Row, Space},
+    window, Alignment, Application, Color, Command, Element, Event, Length, Subscription,
 };
-use image::{imageops, RgbImage};
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
-use tokio::fs;
+use image::DynamicImage;
 
-use crate::himawari::{self, DownloadId, Progress};
+use crate::{
+    config::{Action, Config},
+    export,
+    framing::{self, DiskGeometry},
+    himawari::{self, DownloadId, Progress, Resize, Tiles},
+    quality::{self, Grade},
+};
 
 use self::{
-    downloaded_image::DownloadedImage,
+    archive::{
+        dedupe::{self, Decision},
+        metadata::Metadata,
+        thumbnail, IndexProgress,
+    },
+    compare::{Compare, CompareMode, Comparison},
+    downloaded_image::{DownloadedImage, Source},
     downloading_image::{DownloadState, DownloadingImage},
+    frame_cache::FrameCache,
+    gallery::Gallery,
+    gesture::Gestures,
     modal::Modal,
+    timeline::Timeline,
+    transition::Transition,
+    zoom::{Detail, DetailTile, Viewport, ZoomImage},
 };
 
+pub mod archive;
+mod compare;
 mod downloaded_image;
 mod downloading_image;
+mod frame_cache;
+mod gallery;
+mod gesture;
 mod modal;
+mod timeline;
+mod transition;
+mod zoom;
+
+/// 同時に読み込むサムネイルの数
+const THUMBNAIL_CONCURRENCY: usize = 2;
+/// 拡大・移動を止めてから高解像度のタイルをダウンロードし始めるまでの時間
+const DETAIL_DELAY: Duration = Duration::from_millis(500);
+/// 「昨日と比べる」で、ちょうど24時間前の画像がないときに許すずれ [分]
+const YESTERDAY_TOLERANCE_MINUTES: i64 = 30;
 
 pub struct App {
+    config: Config,
+    /// 撮影時刻順に並んだ保存済みの画像
     images: Vec<DownloadedImage>,
+    /// `images`をまだ読み込み中か
+    is_indexing: bool,
     download: Option<DownloadingImage>,
-    current_image: Option<(usize, iced_image::Handle)>,
+    /// ダウンロードを待っている画像
+    pending_downloads: VecDeque<DownloadId>,
+    /// 索引を読み込み終わってから保存済みか確かめる、最後に見つけた最新の画像
+    deferred_download: Option<DownloadId>,
+    /// 重複として保存しなかった画像。ダウンロードし直さない
+    skipped_downloads: HashSet<DownloadId>,
+    current_image: Option<(DownloadId, iced_image::Handle)>,
+    /// 展開済みのメイン画面の画像
+    frame_cache: FrameCache,
+    /// 撮影時刻と`product`ごとの領域の画像
+    region_images: HashMap<(DownloadId, String), DownloadedImage>,
+    /// メイン画面に表示する領域の`product`。`None`なら全球画像を表示する
+    source: Option<String>,
     shows_menu: bool,
+    /// 直近24時間の画像を順番に表示しているか
+    is_playing: bool,
+    /// 画像を切り替えても保つ
+    viewport: Viewport,
+    /// 表示中のクロスフェード
+    transition: Option<Transition>,
+    /// 最後に頼んだクロスフェードの合成の番号
+    transition_request: u64,
+    /// 表示中の画像の拡大した部分の高解像度のタイル
+    detail: Option<Detail>,
+    /// 最後に予約した高解像度のタイルのダウンロードの番号。古い予約は取りやめる
+    detail_request: u64,
+    /// 比べている2枚の画像。`None`ならいつもの画面を表示する
+    comparison: Option<Comparison>,
+    /// 一覧で選んだ画像を、表示中の画像と比べるか
+    picks_comparison: bool,
+    gallery: Gallery,
+    /// 読み込んだサムネイル。読み込めなかったものは`None`
+    thumbnails: HashMap<DownloadId, Option<iced_image::Handle>>,
+    /// 読み込みを待っているサムネイル
+    thumbnail_queue: VecDeque<DownloadedImage>,
+    /// 読み込み中のサムネイル
+    loading_thumbnails: HashSet<DownloadId>,
 }
 
 #[derive(Debug, Clone)]
@@ -35,32 +110,83 @@ pub enum Message {
     Fetch,
     Download(DownloadId),
     DownloadProgressed(DownloadId, Progress),
-    DownloadCompleted(DownloadedImage),
+    /// 全球画像と、同じタイルから切り出した領域の画像を保存した
+    DownloadCompleted(DownloadedImage, Vec<DownloadedImage>),
+    /// 直前とほぼ同じ画像だったので保存しなかった
+    DownloadSkipped(DownloadId),
+    IndexProgressed(IndexProgress),
     ShowMenu,
     HideMenu,
     SelectImage(DownloadedImage),
+    Compact,
+    Compacted(Vec<DownloadedImage>),
+    ThumbnailLoaded(DownloadId, Option<iced_image::Handle>),
+    GalleryScrolled(f32),
+    /// 日ごとのまとまりを開く・閉じる
+    ToggleDay(NaiveDate),
+    /// 指定した日を含む月のカレンダーを表示する
+    ShowCalendar(NaiveDate),
+    HideCalendar,
+    JumpToDay(NaiveDate),
+    SelectSource(Option<String>),
+    KeyPressed(keyboard::KeyCode),
+    Perform(Action),
+    /// 再生中に次の画像に進む
+    PlaybackAdvanced,
+    Zoomed(Viewport),
+    FetchDetail(u64),
+    DetailLoaded(Detail),
+    /// クロスフェードの途中の画像を合成し終わった。合成できないか間に合わなければ`None`
+    TransitionBlended(u64, Option<Vec<iced_image::Handle>>),
+    TransitionAdvanced(Instant),
+    /// 先読みした画像を展開し終わった。展開できなければ`None`
+    FrameDecoded(DownloadedImage, Option<iced_image::Handle>),
+    /// 一覧で比べる画像を選び始める・やめる
+    PickComparison,
+    /// 表示中の画像と、その24時間前の画像を比べる
+    CompareWithYesterday,
+    ChangeCompareMode(CompareMode),
+    /// 比べる2枚の画像を展開し終わった。展開できなければ`None`
+    ComparisonLoaded(u64, Option<(iced_image::Handle, iced_image::Handle)>),
+    StopComparing,
 }
 
 impl Application for App {
     type Executor = iced::executor::Default;
     type Message = Message;
     type Theme = iced::Theme;
-    type Flags = ();
-
-    fn new(_flags: ()) -> (Self, iced::Command<Self::Message>) {
-        // FIXME: ここが同期なのは不満がある
-        let images = Self::get_images();
-        let current_image = images
-            .iter()
-            .enumerate()
-            .last()
-            .map(|(i, image)| (i, iced_image::Handle::from_path(&image.path)));
+    type Flags = Config;
+
+    fn new(config: Config) -> (Self, iced::Command<Self::Message>) {
+        // 画像の一覧は非同期に読み込むので、ひとまず最後に保存した画像を表示する
+        let current_image = archive::latest().and_then(|image| Some((image.id, image.handle()?)));
+        let frame_cache = FrameCache::new(config.cache.memory_mb * 1024 * 1024);
         (
             App {
-                images,
+                config,
+                images: vec![],
+                is_indexing: true,
                 download: None,
+                pending_downloads: VecDeque::new(),
+                deferred_download: None,
+                skipped_downloads: HashSet::new(),
                 current_image,
+                frame_cache,
+                region_images: HashMap::new(),
+                source: None,
                 shows_menu: false,
+                is_playing: false,
+                viewport: Viewport::default(),
+                transition: None,
+                transition_request: 0,
+                detail: None,
+                detail_request: 0,
+                comparison: None,
+                picks_comparison: false,
+                gallery: Gallery::new(),
+                thumbnails: HashMap::new(),
+                thumbnail_queue: VecDeque::new(),
+                loading_thumbnails: HashSet::new(),
             },
             Command::batch(vec![
                 window::change_mode(window::Mode::Fullscreen),
@@ -78,111 +204,398 @@ impl Application for App {
             Message::None => Command::none(),
             Message::ShowMenu => {
                 self.shows_menu = true;
-                Command::none()
+                self.gallery.reset();
+                self.load_visible_thumbnails()
             }
             Message::HideMenu => {
                 self.shows_menu = false;
+                self.picks_comparison = false;
+                self.thumbnail_queue.clear();
                 Command::none()
             }
+            Message::ThumbnailLoaded(id, handle) => {
+                self.loading_thumbnails.remove(&id);
+                self.thumbnails.insert(id, handle);
+                self.start_thumbnail_loads()
+            }
+            Message::GalleryScrolled(offset) => {
+                self.gallery.scrolled(offset);
+                self.load_visible_thumbnails()
+            }
+            Message::ToggleDay(date) => {
+                self.gallery.toggle_day(date);
+                self.load_visible_thumbnails()
+            }
+            Message::ShowCalendar(date) => {
+                self.gallery.show_calendar(date);
+                Command::none()
+            }
+            Message::HideCalendar => {
+                let command = self.gallery.hide_calendar();
+                Command::batch([command, self.load_visible_thumbnails()])
+            }
+            Message::JumpToDay(date) => {
+                let command = self.gallery.jump_to(date, &self.images);
+                Command::batch([command, self.load_visible_thumbnails()])
+            }
+            Message::SelectImage(image) if self.picks_comparison => {
+                self.picks_comparison = false;
+                let current = self
+                    .current_id()
+                    .and_then(|id| self.images.iter().find(|image| image.id == id))
+                    .cloned();
+                match current {
+                    Some(current) => self.start_comparison(current, image),
+                    None => Command::none(),
+                }
+            }
             Message::SelectImage(image) => {
-                self.current_image = self
-                    .images
-                    .iter()
-                    .enumerate()
-                    .find(|(_, img)| img.id == image.id)
-                    .map(|(i, image)| (i, iced_image::Handle::from_path(&image.path)));
+                self.is_playing = false;
+                let show = self.show(&image);
+                Command::batch([show, self.schedule_detail()])
+            }
+            Message::SelectSource(source) => {
+                self.source = source;
+                let show = self.show_current();
+                Command::batch([show, self.load_comparison(), self.schedule_detail()])
+            }
+            Message::KeyPressed(key_code) => {
+                match self.config.controls.action(&format!("{key_code:?}")) {
+                    Some(action) => self.update(Message::Perform(action)),
+                    None => Command::none(),
+                }
+            }
+            Message::Perform(action) => self.perform(action),
+            Message::Zoomed(viewport) => {
+                self.viewport = viewport;
+                self.schedule_detail()
+            }
+            Message::FetchDetail(request) => {
+                if request != self.detail_request {
+                    return Command::none();
+                }
+                self.fetch_detail()
+            }
+            Message::DetailLoaded(loaded) => {
+                if self.current_id() != Some(loaded.id) {
+                    return Command::none();
+                }
+                match &mut self.detail {
+                    Some(detail) if detail.id == loaded.id && detail.level == loaded.level => {
+                        detail.tiles.extend(loaded.tiles);
+                    }
+                    _ => self.detail = Some(loaded),
+                }
+                // 高解像度のタイルは大きいので、見えなくなったものは捨てる
+                let required = zoom::required_tiles(&self.viewport, &self.disk_geometry());
+                match (&mut self.detail, required) {
+                    (Some(detail), Some((level, positions))) if detail.level == level => {
+                        detail
+                            .tiles
+                            .retain(|tile| positions.contains(&(tile.x, tile.y)));
+                    }
+                    _ => self.detail = None,
+                }
                 Command::none()
             }
-            Message::Fetch => {
-                Command::perform(himawari::fetch_download_info(), |result| match result {
-                    Ok(info) => Message::Download(info),
-                    Err(e) => {
-                        log::error!("failed to fetch image: {e}");
-                        Message::None
+            Message::PickComparison => {
+                self.picks_comparison = !self.picks_comparison;
+                Command::none()
+            }
+            Message::CompareWithYesterday => {
+                let Some(id) = self.current_id() else {
+                    return Command::none();
+                };
+                let Some(current) = self.images.iter().find(|image| image.id == id).cloned() else {
+                    return Command::none();
+                };
+                // 24時間前に最も近い画像
+                let target = id.as_utc_datetime() - chrono::Duration::hours(24);
+                let yesterday = self
+                    .displayable_images()
+                    .map(|image| (image, (image.id.as_utc_datetime() - target).abs()))
+                    .filter(|(_, distance)| {
+                        *distance <= chrono::Duration::minutes(YESTERDAY_TOLERANCE_MINUTES)
+                    })
+                    .min_by_key(|(_, distance)| *distance)
+                    .map(|(image, _)| image.clone());
+                match yesterday {
+                    Some(yesterday) => self.start_comparison(current, yesterday),
+                    None => {
+                        log::warn!("No image around {target}");
+                        Command::none()
                     }
-                })
+                }
             }
-            Message::Download(id) => {
-                if let Some(image) = self.images.iter().find(|image| image.id == id) {
-                    log::debug!("Already downloaded: {}", image.path.display());
+            Message::ChangeCompareMode(mode) => {
+                if let Some(comparison) = &mut self.comparison {
+                    comparison.mode = mode;
+                }
+                Command::none()
+            }
+            Message::ComparisonLoaded(request, handles) => {
+                let Some(comparison) = &mut self.comparison else {
+                    return Command::none();
+                };
+                if comparison.request != request {
                     return Command::none();
                 }
-                self.download = Some(DownloadingImage::new(id));
+                match handles {
+                    Some(handles) => comparison.handles = Some(handles),
+                    None => self.comparison = None,
+                }
                 Command::none()
             }
-            Message::DownloadProgressed(_, Progress::Started) => {
-                self.download.as_mut().unwrap().state =
-                    DownloadState::Downloading { progress: 0.0 };
+            Message::StopComparing => {
+                self.comparison = None;
                 Command::none()
             }
-            Message::DownloadProgressed(_, Progress::Advanced(progress)) => {
-                self.download.as_mut().unwrap().state = DownloadState::Downloading { progress };
+            Message::PlaybackAdvanced => {
+                // 最後まで進んだら24時間前に戻る
+                let image = self
+                    .recent_images()
+                    .find(|image| self.current_id().is_some_and(|id| image.id > id))
+                    .or_else(|| self.recent_images().next())
+                    .cloned();
+                match image {
+                    Some(image) => self.show(&image),
+                    None => {
+                        self.is_playing = false;
+                        Command::none()
+                    }
+                }
+            }
+            Message::TransitionBlended(request, frames) => {
+                let Some(transition) = self
+                    .transition
+                    .as_mut()
+                    .filter(|transition| transition.request == request)
+                else {
+                    return Command::none();
+                };
+                match frames {
+                    Some(frames) => transition.start(frames),
+                    None => self.transition = None,
+                }
+                Command::none()
+            }
+            Message::FrameDecoded(image, handle) => {
+                // 展開を待っていた表示中の画像を差し替える
+                if let Some(handle) = &handle {
+                    if self.is_shown(&image) {
+                        self.current_image = Some((image.id, handle.clone()));
+                    }
+                }
+                let next = self.frame_cache.loaded(&image, handle);
+                self.decode_frame(next)
+            }
+            Message::TransitionAdvanced(now) => {
+                if let Some(transition) = &mut self.transition {
+                    if !transition.tick(now) {
+                        self.transition = None;
+                    }
+                }
                 Command::none()
             }
-            Message::DownloadProgressed(_, Progress::Failed(e)) => {
-                log::error!("failed to download image: {e}");
-                self.download.as_mut().unwrap().state = DownloadState::Failed(e);
+            Message::IndexProgressed(IndexProgress::Indexed { images, broken }) => {
+                self.insert_images(images);
+                // 壊れていた画像はダウンロードし直す
+                for id in broken {
+                    self.enqueue_download(id);
+                }
                 Command::none()
             }
-            Message::DownloadProgressed(timestamp, Progress::Finished(data)) => {
-                self.download.as_mut().unwrap().state = DownloadState::Finished;
+            Message::IndexProgressed(IndexProgress::Finished { skipped }) => {
+                self.is_indexing = false;
+                self.skipped_downloads.extend(skipped);
+                let mut commands = vec![Command::perform(async {}, |_| Message::Compact)];
+                if self.current_image.is_none() {
+                    let latest = self.displayable_images().next_back().cloned();
+                    if let Some(image) = latest {
+                        commands.push(self.show(&image));
+                    }
+                }
+                if let Some(id) = self.deferred_download.take() {
+                    commands.push(self.update(Message::Download(id)));
+                }
+                Command::batch(commands)
+            }
+            Message::Compact => {
+                let storage = &self.config.storage;
+                let Some(days) = storage.bundle_after_days else {
+                    return Command::none();
+                };
                 Command::perform(
-                    App::resize_and_save_image(timestamp, data),
+                    archive::compact(storage.layout, days),
                     |result| match result {
-                        Ok(image) => Message::DownloadCompleted(image),
+                        Ok(images) => Message::Compacted(images),
                         Err(e) => {
-                            log::error!("failed to resize image: {e}");
+                            log::error!("failed to compact images: {e}");
                             Message::None
                         }
                     },
                 )
             }
-            Message::DownloadCompleted(image) => {
-                self.download = None;
-                self.images.push(image);
-                if let Some((i, _)) = self.current_image {
-                    // current_imageが最新の画像だったら新しい画像に追従する
-                    if i == self.images.len() - 2 {
-                        self.current_image = self
-                            .images
-                            .iter()
-                            .enumerate()
-                            .last()
-                            .map(|(i, image)| (i, iced_image::Handle::from_path(&image.path)));
+            Message::Compacted(images) => {
+                let compacted = images.iter().map(|image| image.id).collect::<HashSet<_>>();
+                self.images.retain(|image| !compacted.contains(&image.id));
+                self.insert_images(images);
+                // まとめたファイルから読むように差し替える
+                self.show_current()
+            }
+            Message::Fetch => {
+                Command::perform(himawari::fetch_download_info(), |result| match result {
+                    Ok(info) => Message::Download(info),
+                    Err(e) => {
+                        log::error!("failed to fetch image: {e}");
+                        Message::None
                     }
-                } else {
-                    self.current_image = self
-                        .images
-                        .iter()
-                        .enumerate()
-                        .last()
-                        .map(|(i, image)| (i, iced_image::Handle::from_path(&image.path)));
+                })
+            }
+            Message::Download(id) => {
+                // 保存済みかは索引で判断するので、読み込み終わるまで待つ
+                if self.is_indexing {
+                    self.deferred_download = Some(id);
+                    return Command::none();
+                }
+                let is_saved = self
+                    .images
+                    .binary_search_by_key(&id, |image| image.id)
+                    .is_ok();
+                if is_saved || self.skipped_downloads.contains(&id) {
+                    log::debug!("Already downloaded: {id:?}");
+                    return Command::none();
                 }
+                self.enqueue_download(id);
                 Command::none()
             }
+            Message::DownloadProgressed(id, progress) => {
+                // 取りやめたダウンロードの通知は捨てる
+                let Some(download) = self.download.as_mut().filter(|download| download.id == id)
+                else {
+                    log::debug!("Discard progress of stale download: {id:?}");
+                    return Command::none();
+                };
+                match progress {
+                    Progress::Started | Progress::Advanced(_) => {
+                        download.state = DownloadState::Downloading;
+                        Command::none()
+                    }
+                    Progress::TileFinished { x, y, tile } => {
+                        let level = himawari::DOWNLOAD_LEVEL;
+                        let tile = DetailTile { x, y, handle: tile };
+                        download
+                            .preview
+                            .get_or_insert_with(|| Detail {
+                                id,
+                                level,
+                                tiles: vec![],
+                            })
+                            .tiles
+                            .push(tile);
+                        Command::none()
+                    }
+                    Progress::Failed(e) => {
+                        log::error!("failed to download image: {e}");
+                        download.state = DownloadState::Failed;
+                        self.start_next_download();
+                        Command::none()
+                    }
+                    Progress::Finished(tiles) => {
+                        download.state = DownloadState::Finished;
+                        Command::perform(
+                            App::resize_and_save_image(self.config.clone(), id, *tiles),
+                            move |result| match result {
+                                Ok(Some((image, regions))) => {
+                                    Message::DownloadCompleted(image, regions)
+                                }
+                                Ok(None) => Message::DownloadSkipped(id),
+                                Err(e) => {
+                                    log::error!("failed to resize image: {e}");
+                                    Message::DownloadProgressed(id, Progress::Failed(Arc::new(e)))
+                                }
+                            },
+                        )
+                    }
+                }
+            }
+            Message::DownloadSkipped(id) => {
+                self.skipped_downloads.insert(id);
+                self.download = None;
+                self.start_next_download();
+                Command::none()
+            }
+            Message::DownloadCompleted(image, regions) => {
+                self.download = None;
+                self.start_next_download();
+                // 品質の悪い画像には追従しない
+                let follows = !image.is_bad() && self.follows_latest();
+                // 領域を選んでいれば同じ時刻の領域の画像を表示するので、表示する前に加える
+                self.insert_images(regions);
+                let show = if follows {
+                    self.show(&image)
+                } else {
+                    Command::none()
+                };
+                self.insert_images(vec![image]);
+                if self.shows_menu {
+                    Command::batch([show, self.load_visible_thumbnails()])
+                } else {
+                    show
+                }
+            }
         }
     }
 
-    fn view(&self) -> iced::Element<Message> {
+    fn view(&self) -> iced::Element<'_, Message> {
+        if let Some(comparison) = &self.comparison {
+            return self.comparison_view(comparison);
+        }
         let Some((_, handle)) = &self.current_image else {
             return Space::new(Length::Fill, Length::Fill).into();
         };
+        let handle = self.transition.as_ref().map_or(handle, Transition::frame);
 
-        let content = button(
-            iced_image::Image::new(handle.clone())
-                .width(Length::Fill)
-                .height(Length::Fill),
+        let controls = &self.config.controls;
+        // ダウンロード中の画像に切り替わる予定なら、届いたタイルを重ねる
+        let preview = self
+            .download
+            .as_ref()
+            .filter(|download| !download.is_failed())
+            .and_then(|download| download.preview.as_ref())
+            .filter(|_| self.follows_latest() && self.transition.is_none());
+        let detail = self
+            .detail
+            .as_ref()
+            .filter(|detail| Some(detail.id) == self.current_id());
+        let detail = preview.or(detail).filter(|_| self.source.is_none());
+        let content = Gestures::new(
+            ZoomImage::new(
+                handle.clone(),
+                self.viewport,
+                self.disk_geometry(),
+                Message::Zoomed,
+            )
+            .detail(detail),
+            |gesture| match controls.action(gesture.name()) {
+                Some(action) => Message::Perform(action),
+                None => Message::None,
+            },
         )
-        .on_press(Message::ShowMenu)
-        .width(Length::Fill)
-        .height(Length::Fill)
-        .padding(0)
-        .style(theme::Button::Text);
+        // 拡大しているときはドラッグで画像を動かす
+        .swipes(!self.viewport.is_zoomed());
 
         if self.shows_menu {
             Modal::new(content, self.menu()).into()
         } else {
-            content.into()
+            let current = self
+                .current_image
+                .as_ref()
+                .map(|(id, _)| id.as_utc_datetime());
+            Timeline::new(content, &self.images, current, |image| {
+                Message::SelectImage(image.clone())
+            })
+            .into()
         }
     }
 
@@ -193,8 +606,43 @@ impl Application for App {
             .as_ref()
             .map(DownloadingImage::subscription)
             .into_iter();
+        let index = self.is_indexing.then(|| {
+            archive::index_subscription(self.config.storage.layout).map(Message::IndexProgressed)
+        });
+        // 1日に1回、古い画像をまとめる
+        let compact =
+            (!self.is_indexing && self.config.storage.bundle_after_days.is_some()).then(|| {
+                iced::time::every(Duration::from_secs(24 * 60 * 60)).map(|_| Message::Compact)
+            });
+
+        let playback = self.is_playing.then(|| {
+            iced::time::every(Duration::from_millis(
+                self.config.controls.playback_interval_ms,
+            ))
+            .map(|_| Message::PlaybackAdvanced)
+        });
+        let transition = self
+            .transition
+            .as_ref()
+            .filter(|transition| transition.is_running())
+            .map(|_| window::frames().map(Message::TransitionAdvanced));
+        let keys = subscription::events_with(|event, status| match (event, status) {
+            (
+                Event::Keyboard(keyboard::Event::KeyPressed { key_code, .. }),
+                event::Status::Ignored,
+            ) => Some(Message::KeyPressed(key_code)),
+            _ => None,
+        });
 
-        Subscription::batch(progress.chain(fetch))
+        Subscription::batch(
+            progress
+                .chain(fetch)
+                .chain(index)
+                .chain(compact)
+                .chain(playback)
+                .chain(transition)
+                .chain(iter::once(keys)),
+        )
     }
 
     fn theme(&self) -> Self::Theme {
@@ -203,116 +651,696 @@ impl Application for App {
 }
 
 impl App {
-    const IMAGE_DIR: &'static str = "./images";
-    const IMAGE_SIZE: u32 = 1080;
-
-    fn get_images() -> Vec<DownloadedImage> {
-        match read_dir(Self::IMAGE_DIR) {
-            Ok(paths) => {
-                let mut images = paths
-                    .filter_map(|path| {
-                        let path = path.ok()?.path();
-                        let file_name = path.file_name()?.to_str()?;
-                        let Ok(timestamp) =
-                            NaiveDateTime::parse_from_str(file_name, "%Y%m%d%H%M%S.png")
-                        else {
-                            log::warn!("unexpected filename: {file_name}");
-                            return None;
-                        };
-
-                        Some(DownloadedImage {
-                            path,
-                            id: DownloadId::new(timestamp.and_utc()),
-                        })
-                    })
-                    .collect::<Vec<_>>();
-                images.sort_by(|i1, i2| i1.id.cmp(&i2.id));
-                images
+    fn current_id(&self) -> Option<DownloadId> {
+        self.current_image.as_ref().map(|(id, _)| *id)
+    }
+
+    /// 表示中の画像の中での全球画像の配置
+    ///
+    /// 記録されていない古い画像は、いまの設定で保存されたものとみなす。
+    fn disk_geometry(&self) -> DiskGeometry {
+        let recorded = self.current_id().and_then(|id| {
+            let i = self
+                .images
+                .binary_search_by_key(&id, |image| image.id)
+                .ok()?;
+            self.images[i].disk
+        });
+        recorded.unwrap_or_else(|| {
+            let output = &self.config.output;
+            let size = framing::disk_size(output.fit, output.width, output.height);
+            DiskGeometry::centered(output.fit, size, output.width, output.height)
+        })
+    }
+
+    /// `current_image`が最新の画像で、新しくダウンロードした画像に追従するか
+    fn follows_latest(&self) -> bool {
+        match &self.current_image {
+            Some((id, _)) => self
+                .images
+                .iter()
+                .rfind(|image| !image.is_bad())
+                .is_none_or(|last| *id >= last.id),
+            None => true,
+        }
+    }
+
+    /// メイン画面に表示できる画像
+    fn displayable_images(&self) -> impl DoubleEndedIterator<Item = &DownloadedImage> {
+        self.images.iter().filter(|image| !image.is_bad())
+    }
+
+    /// 再生する直近24時間の画像
+    fn recent_images(&self) -> impl Iterator<Item = &DownloadedImage> {
+        let start = Utc::now() - chrono::Duration::hours(24);
+        self.displayable_images()
+            .filter(move |image| image.id.as_utc_datetime() >= start)
+    }
+
+    /// キーやジェスチャーに割り当てた操作を行う
+    fn perform(&mut self, action: Action) -> Command<Message> {
+        let current_id = self.current_id();
+        let image = match action {
+            Action::Previous => self
+                .displayable_images()
+                .rfind(|image| current_id.is_some_and(|id| image.id < id)),
+            Action::Next => self
+                .displayable_images()
+                .find(|image| current_id.is_some_and(|id| image.id > id)),
+            Action::Oldest => self.displayable_images().next(),
+            Action::Newest => self.displayable_images().next_back(),
+            Action::ResetZoom => {
+                self.viewport = Viewport::default();
+                return Command::none();
+            }
+            Action::TogglePlayback => {
+                self.is_playing = !self.is_playing;
+                if !self.is_playing {
+                    return self.schedule_detail();
+                }
+                // 最新の画像を表示していたら24時間前から再生する
+                let is_latest = self
+                    .displayable_images()
+                    .next_back()
+                    .is_some_and(|last| Some(last.id) == current_id);
+                if self.is_playing && is_latest {
+                    return self.update(Message::PlaybackAdvanced);
+                }
+                return Command::none();
+            }
+            Action::CloseMenu if self.comparison.is_some() => {
+                return self.update(Message::StopComparing)
+            }
+            Action::OpenMenu if !self.shows_menu => return self.update(Message::ShowMenu),
+            Action::CloseMenu if self.shows_menu => return self.update(Message::HideMenu),
+            Action::OpenMenu | Action::CloseMenu => return Command::none(),
+        };
+        match image.cloned() {
+            Some(image) => self.update(Message::SelectImage(image)),
+            None => Command::none(),
+        }
+    }
+
+    /// 新しく見つかった画像を`images`と`region_images`に加える
+    fn insert_images(&mut self, images: Vec<DownloadedImage>) {
+        let (images, regions): (Vec<_>, Vec<_>) = images
+            .into_iter()
+            .partition(|image| image.product.is_none());
+        for image in regions {
+            let product = image.product.clone().unwrap_or_default();
+            self.region_images.insert((image.id, product), image);
+        }
+        self.images.extend(images);
+        self.images.sort_by_key(|image| image.id);
+        self.images.dedup_by_key(|image| image.id);
+    }
+
+    /// 全球画像`image`を表示する。別の時刻の画像からはクロスフェードで切り替える
+    fn show(&mut self, image: &DownloadedImage) -> Command<Message> {
+        let forward = self.current_id().is_none_or(|id| id <= image.id);
+        let prefetch = self.prefetch(image, forward);
+        let Some(to) = self.handle_for(image) else {
+            // 展開し終わるまでは、いま見えている画像を表示したままにする
+            let shown = self.shown_handle();
+            self.current_image = Some((image.id, shown));
+            return prefetch;
+        };
+        let previous = self.current_image.replace((image.id, to.clone()));
+        let config = &self.config.transition;
+        // 再生中や、前の画像からの合成が終わらないうちに切り替えたときは、合成を待たずにすぐ表示する
+        let is_blending = self
+            .transition
+            .as_ref()
+            .is_some_and(Transition::is_blending);
+        let fades =
+            config.duration_ms > 0 && !self.is_playing && !is_blending && self.comparison.is_none();
+        let from = match previous {
+            Some((id, handle)) if id != image.id && fades => {
+                // クロスフェードの途中なら、いま見えている画像から切り替える
+                match &self.transition {
+                    Some(transition) => transition.frame().clone(),
+                    None => handle,
+                }
+            }
+            _ => {
+                // 捨てたクロスフェードの合成は途中でやめる
+                self.transition = None;
+                return prefetch;
+            }
+        };
+        self.transition_request += 1;
+        let request = self.transition_request;
+        let transition = Transition::new(request, from, Duration::from_millis(config.duration_ms));
+        let blend = Command::perform(
+            transition.blend(to, config.frames, config.interpolate),
+            move |result| match result {
+                Ok(frames) => Message::TransitionBlended(request, frames),
+                Err(e) => {
+                    log::warn!("failed to blend images: {e}");
+                    Message::TransitionBlended(request, None)
+                }
+            },
+        );
+        self.transition = Some(transition);
+        Command::batch([blend, prefetch])
+    }
+
+    /// `image`と、そこから進む向きにある画像を先読みする
+    fn prefetch(&mut self, image: &DownloadedImage, forward: bool) -> Command<Message> {
+        let count = self.config.cache.prefetch;
+        let neighbours: Vec<_> = if forward {
+            self.displayable_images()
+                .filter(|other| other.id > image.id)
+                .take(count)
+                .collect()
+        } else {
+            self.displayable_images()
+                .rev()
+                .filter(|other| other.id < image.id)
+                .take(count)
+                .collect()
+        };
+        let images = iter::once(image)
+            .chain(neighbours)
+            .map(|image| self.displayed_image(image).clone())
+            .collect();
+        let next = self.frame_cache.prefetch(images);
+        self.decode_frame(next)
+    }
+
+    fn decode_frame(&self, image: Option<DownloadedImage>) -> Command<Message> {
+        let Some(image) = image else {
+            return Command::none();
+        };
+        Command::perform(
+            async move {
+                let handle = frame_cache::decode(image.clone()).await;
+                (image, handle)
+            },
+            |(image, result)| match result {
+                Ok(handle) => Message::FrameDecoded(image, Some(handle)),
+                Err(e) => {
+                    log::error!("failed to decode image: {e}");
+                    Message::FrameDecoded(image, None)
+                }
+            },
+        )
+    }
+
+    /// 全球画像`image`の代わりに表示する画像。領域を選んでいる場合は、同じ時刻の領域の画像があればそちらにする
+    fn displayed_image<'a>(&'a self, image: &'a DownloadedImage) -> &'a DownloadedImage {
+        let region = self
+            .source
+            .clone()
+            .and_then(|product| self.region_images.get(&(image.id, product)));
+        region.unwrap_or(image)
+    }
+
+    /// 表示用のハンドル。展開済みならそれを使う。すぐに表示できなければ`None`
+    fn handle_for(&self, image: &DownloadedImage) -> Option<iced_image::Handle> {
+        let image = self.displayed_image(image);
+        match self.frame_cache.get(image) {
+            Some(handle) => Some(handle.clone()),
+            None => image.handle(),
+        }
+    }
+
+    /// `image`がいま表示している画像か
+    fn is_shown(&self, image: &DownloadedImage) -> bool {
+        if self.current_id() != Some(image.id) {
+            return false;
+        }
+        let Ok(i) = self
+            .images
+            .binary_search_by_key(&image.id, |image| image.id)
+        else {
+            return false;
+        };
+        self.displayed_image(&self.images[i]).product == image.product
+    }
+
+    /// いま見えている画像のハンドル。クロスフェードはやめる
+    fn shown_handle(&mut self) -> iced_image::Handle {
+        self.transition
+            .take()
+            .map(|transition| transition.frame().clone())
+            .or_else(|| self.current_image.take().map(|(_, handle)| handle))
+            // 何も表示していなければ透明な画像にする
+            .unwrap_or_else(|| iced_image::Handle::from_pixels(1, 1, vec![0; 4]))
+    }
+
+    /// メニューを閉じて2枚の画像を比べる
+    fn start_comparison(&mut self, a: DownloadedImage, b: DownloadedImage) -> Command<Message> {
+        self.is_playing = false;
+        self.comparison = Some(Comparison::new(a, b));
+        let load = self.load_comparison();
+        Command::batch([load, self.update(Message::HideMenu)])
+    }
+
+    /// 比べる2枚の画像を裏で展開する。展開済みの画像はそのまま使う
+    fn load_comparison(&mut self) -> Command<Message> {
+        let Some(comparison) = &self.comparison else {
+            return Command::none();
+        };
+        let [before, after] = [&comparison.before, &comparison.after].map(|image| {
+            let image = self.displayed_image(image).clone();
+            let cached = self.frame_cache.get(&image).cloned();
+            async move {
+                match cached {
+                    Some(handle) => Ok(handle),
+                    None => frame_cache::decode(image).await,
+                }
             }
+        });
+        let Some(comparison) = &mut self.comparison else {
+            return Command::none();
+        };
+        comparison.request += 1;
+        comparison.handles = None;
+        let request = comparison.request;
+        Command::perform(
+            futures::future::try_join(before, after),
+            move |result| match result {
+                Ok(handles) => Message::ComparisonLoaded(request, Some(handles)),
+                Err(e) => {
+                    log::error!("failed to load images to compare: {e}");
+                    Message::ComparisonLoaded(request, None)
+                }
+            },
+        )
+    }
+
+    /// 表示中の画像を読み込み直す
+    fn show_current(&mut self) -> Command<Message> {
+        let Some(id) = self.current_id() else {
+            return Command::none();
+        };
+        let Ok(i) = self.images.binary_search_by_key(&id, |image| image.id) else {
+            return Command::none();
+        };
+        let image = self.images[i].clone();
+        let handle = match self.handle_for(&image) {
+            Some(handle) => {
+                self.transition = None;
+                handle
+            }
+            // 展開し終わるまでは、いま見えている画像を表示したままにする
+            None => self.shown_handle(),
+        };
+        self.current_image = Some((id, handle));
+        self.prefetch(&image, true)
+    }
+
+    /// 一覧に見えている画像のうち、まだ読み込んでいないサムネイルを読み込む
+    ///
+    /// スクロールして見えなくなった画像の読み込みは取りやめる。
+    fn load_visible_thumbnails(&mut self) -> Command<Message> {
+        self.thumbnail_queue = self
+            .gallery
+            .visible_images(&self.images)
+            .into_iter()
+            .filter(|image| {
+                !self.thumbnails.contains_key(&image.id)
+                    && !self.loading_thumbnails.contains(&image.id)
+            })
+            .collect();
+        self.start_thumbnail_loads()
+    }
+
+    /// Piで重くならないように、少しずつサムネイルを読み込む
+    fn start_thumbnail_loads(&mut self) -> Command<Message> {
+        let mut commands = vec![];
+        while self.loading_thumbnails.len() < THUMBNAIL_CONCURRENCY {
+            let Some(image) = self.thumbnail_queue.pop_front() else {
+                break;
+            };
+            let id = image.id;
+            self.loading_thumbnails.insert(id);
+            commands.push(Command::perform(
+                thumbnail::load(image),
+                move |result| match result {
+                    Ok(data) => {
+                        Message::ThumbnailLoaded(id, Some(iced_image::Handle::from_memory(data)))
+                    }
+                    Err(e) => {
+                        log::error!("failed to load thumbnail: {e}");
+                        Message::ThumbnailLoaded(id, None)
+                    }
+                },
+            ));
+        }
+        Command::batch(commands)
+    }
+
+    /// 拡大・移動が落ち着いたら、見えている範囲の高解像度のタイルをダウンロードする
+    ///
+    /// 再生中は画像がすぐに切り替わるのでダウンロードしない。
+    fn schedule_detail(&mut self) -> Command<Message> {
+        if self.is_playing || !self.viewport.is_zoomed() {
+            return Command::none();
+        }
+        self.detail_request += 1;
+        let request = self.detail_request;
+        Command::perform(tokio::time::sleep(DETAIL_DELAY), move |_| {
+            Message::FetchDetail(request)
+        })
+    }
+
+    fn fetch_detail(&mut self) -> Command<Message> {
+        // 領域の画像は全球画像と位置が違うので重ねられない
+        let (Some(id), None) = (self.current_id(), &self.source) else {
+            return Command::none();
+        };
+        let Some((level, positions)) = zoom::required_tiles(&self.viewport, &self.disk_geometry())
+        else {
+            return Command::none();
+        };
+        // すでにあるタイルはダウンロードしない
+        let loaded = self
+            .detail
+            .as_ref()
+            .filter(|detail| detail.id == id && detail.level == level)
+            .map(|detail| &detail.tiles[..])
+            .unwrap_or_default();
+        let positions = positions
+            .into_iter()
+            .filter(|(x, y)| !loaded.iter().any(|tile| tile.x == *x && tile.y == *y))
+            .collect::<Vec<_>>();
+        if positions.is_empty() {
+            return Command::none();
+        }
+        log::info!("Fetch {} detail tiles at level {level}", positions.len());
+        let fetch = async move {
+            let tiles = himawari::fetch_tiles(id, level, positions).await?;
+            anyhow::Ok(Detail {
+                id,
+                level,
+                tiles: tiles
+                    .iter()
+                    .map(|(x, y, tile)| DetailTile::new(level, *x, *y, tile))
+                    .collect(),
+            })
+        };
+        Command::perform(fetch, |result| match result {
+            Ok(detail) => Message::DetailLoaded(detail),
             Err(e) => {
-                log::error!("{e}");
-                vec![]
+                log::error!("failed to fetch detail tiles: {e}");
+                Message::None
             }
+        })
+    }
+
+    /// ダウンロード待ちに加え、ダウンロード中でなければ始める
+    fn enqueue_download(&mut self, id: DownloadId) {
+        let is_downloading = self
+            .download
+            .as_ref()
+            .is_some_and(|download| download.id == id && !download.is_failed());
+        if is_downloading || self.pending_downloads.contains(&id) {
+            return;
+        }
+        self.pending_downloads.push_back(id);
+        self.start_next_download();
+    }
+
+    /// 失敗したダウンロードは次のダウンロードで置き換える
+    fn start_next_download(&mut self) {
+        if self
+            .download
+            .as_ref()
+            .is_some_and(|download| !download.is_failed())
+        {
+            return;
+        }
+        if let Some(id) = self.pending_downloads.pop_front() {
+            let resize = Resize::for_config(&self.config);
+            self.download = Some(DownloadingImage::new(id, resize));
         }
     }
 
     async fn resize_and_save_image(
+        config: Config,
         id: DownloadId,
-        datas: [Vec<u8>; 4],
-    ) -> anyhow::Result<DownloadedImage> {
-        log::info!("Load images");
-        let images = datas
-            .iter()
-            .map(|d| image::load_from_memory(d))
-            .collect::<Result<Vec<_>, _>>()?;
-
-        log::info!("Resize images");
-        let images = images
-            .into_par_iter()
-            .map(|image| {
-                image.resize(
-                    App::IMAGE_SIZE / 2,
-                    App::IMAGE_SIZE / 2,
-                    imageops::FilterType::Lanczos3,
-                )
-            })
-            .collect::<Vec<_>>();
+        mut tiles: Tiles,
+    ) -> anyhow::Result<Option<(DownloadedImage, Vec<DownloadedImage>)>> {
+        // タイルはダウンロードしながら縮小してつなぎ合わせてある
+        let mut combined = mem::take(&mut tiles.image);
+        let output = &config.output;
 
-        log::info!("Combine images");
-        let mut combined = RgbImage::new(App::IMAGE_SIZE, App::IMAGE_SIZE);
-        for (i, image) in images.into_iter().enumerate() {
-            let x = (App::IMAGE_SIZE / 2) as i64 * (i / 2) as i64;
-            let y = (App::IMAGE_SIZE / 2) as i64 * (i % 2) as i64;
-            imageops::replace(&mut combined, &image.to_rgb8(), x, y);
+        // 画像処理で明るさが変わる前に調べる
+        let quality = quality::assess(&combined, id.as_utc_datetime());
+        if quality.grade != Grade::Good {
+            log::warn!("{:?} frame: {}", quality.grade, quality.reasons.join(", "));
         }
 
-        log::info!("Save image");
-        let image_path = Path::new(&format!(
-            "{}/{}.png",
-            App::IMAGE_DIR,
-            id.as_utc_datetime().format("%Y%m%d%H%M%S")
-        ))
-        .to_path_buf();
-        if fs::metadata(App::IMAGE_DIR).await.is_err() {
-            fs::create_dir(App::IMAGE_DIR).await?;
+        for stage in &config.processing.stages {
+            log::info!("Process image: {stage:?}");
+            stage.apply(&mut combined);
+        }
+
+        let decision = dedupe::check(id, &combined, &config.storage.dedupe).await?;
+        if decision == Decision::Skipped {
+            return Ok(None);
         }
-        combined.save(&image_path)?;
+
+        log::info!("Compose image");
+        let geometry =
+            DiskGeometry::centered(output.fit, combined.width(), output.width, output.height);
+        let composed = framing::compose(&combined, &geometry, &output.background)?;
+
+        log::info!("Save image");
+        let metadata = Metadata {
+            id,
+            product: None,
+            level: Some(tiles.level),
+            processing: Some(serde_json::to_string(&config.processing)?),
+            quality: Some(quality.clone()),
+            disk: Some(geometry),
+        };
+        let image_path = archive::save(&composed, &metadata, Some(&tiles), &config.storage).await?;
         log::info!("Image saved: {}", image_path.display());
+        // サムネイルは一覧を開いたときにも作れるので、失敗しても保存は続ける
+        if let Err(e) = thumbnail::save(id, &composed).await {
+            log::error!("failed to save thumbnail: {e}");
+        }
+
+        if config.equirect.auto {
+            let map = export::equirect::reproject(
+                &combined,
+                &DiskGeometry::full_disk(combined.width()),
+                &config.equirect.bounds,
+                config.equirect.resolution,
+            );
+            let map = DynamicImage::ImageRgba8(map);
+            let metadata = Metadata {
+                product: Some("equirect".to_string()),
+                disk: None,
+                ..metadata.clone()
+            };
+            let map_path = archive::save(&map, &metadata, Some(&tiles), &config.storage).await?;
+            log::info!("Map saved: {}", map_path.display());
+        }
+
+        let regions = App::save_regions(&config, id, &tiles, &metadata).await?;
 
-        Ok(DownloadedImage {
-            path: image_path,
+        let image = DownloadedImage {
+            source: Source::File(image_path),
             id,
-        })
+            product: None,
+            quality: Some(quality),
+            disk: Some(geometry),
+        };
+        // 品質の悪い画像は次回起動時にも表示しない
+        if !image.is_bad() {
+            archive::set_latest(&image).await?;
+        }
+        Ok(Some((image, regions)))
+    }
+
+    /// 設定された領域を全球画像と同じタイルから切り出して保存する
+    ///
+    /// 解像度が足りない領域は、足りないタイルだけをダウンロードする。
+    async fn save_regions(
+        config: &Config,
+        id: DownloadId,
+        tiles: &Tiles,
+        metadata: &Metadata,
+    ) -> anyhow::Result<Vec<DownloadedImage>> {
+        let output = &config.output;
+        let filter = config.processing.resize_filter;
+        let mut regions = vec![];
+        for region in &config.regions {
+            log::info!("Render region: {}", region.name);
+            let (level, mut image) = region
+                .render(id, tiles, output.width, output.height, filter)
+                .await?;
+            for stage in &config.processing.stages {
+                stage.apply(&mut image);
+            }
+            let product = region.product();
+            let metadata = Metadata {
+                product: Some(product.clone()),
+                level: Some(level),
+                quality: None,
+                disk: None,
+                ..metadata.clone()
+            };
+            let image = DynamicImage::ImageRgb8(image);
+            let path = archive::save(&image, &metadata, None, &config.storage).await?;
+            log::info!("Region saved: {}", path.display());
+            regions.push(DownloadedImage {
+                source: Source::File(path),
+                id,
+                product: Some(product),
+                quality: None,
+                disk: None,
+            });
+        }
+        Ok(regions)
+    }
+
+    /// メイン画面に表示する全球画像と領域を選ぶボタン。領域を設定していなければ何も表示しない
+    fn source_selector(&self) -> Element<'_, Message> {
+        if self.config.regions.is_empty() {
+            return Space::new(0, 0).into();
+        }
+        let sources = iter::once(("Full Disk".to_string(), None)).chain(
+            self.config
+                .regions
+                .iter()
+                .map(|region| (region.name.clone(), Some(region.product()))),
+        );
+        let buttons = sources.map(|(name, source)| {
+            let color = if source == self.source {
+                Color::from_rgb8(0xff, 0xf1, 0x00) // Yellow
+            } else {
+                Color::WHITE
+            };
+            button(text(name).size(24).style(theme::Text::Color(color)))
+                .on_press(Message::SelectSource(source))
+                .style(theme::Button::Text)
+                .into()
+        });
+        Row::with_children(buttons.collect()).spacing(20).into()
+    }
+
+    /// 表示中の画像と比べる画像を選ぶボタン
+    fn compare_buttons(&self) -> Element<'_, Message> {
+        let pick_color = if self.picks_comparison {
+            Color::from_rgb8(0xff, 0xf1, 0x00) // Yellow
+        } else {
+            Color::WHITE
+        };
+        let pick_label = if self.picks_comparison {
+            "Select an image…"
+        } else {
+            "Compare"
+        };
+        row![
+            button(
+                text(pick_label)
+                    .size(24)
+                    .style(theme::Text::Color(pick_color))
+            )
+            .on_press(Message::PickComparison)
+            .style(theme::Button::Text),
+            button(text("Yesterday").size(24))
+                .on_press(Message::CompareWithYesterday)
+                .style(theme::Button::Text),
+        ]
+        .spacing(20)
+        .into()
     }
 
-    fn menu(&self) -> Element<Message> {
-        let current_index = self.current_image.as_ref().map(|(i, _)| i);
-        let images =
-            scrollable(
-                Column::with_children(
-                    self.download
-                        .iter()
-                        .map(DownloadingImage::view)
-                        .chain(self.images.iter().enumerate().rev().map(|(i, image)| {
-                            DownloadedImage::view(image, current_index == Some(&i))
-                        }))
-                        .collect(),
+    /// 2枚の画像を比べる画面。古い画像を左に表示する
+    fn comparison_view(&self, comparison: &Comparison) -> Element<'_, Message> {
+        let (before, after) = (&comparison.before, &comparison.after);
+        let time = |image: &DownloadedImage| {
+            image
+                .id
+                .as_local_datetime()
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        };
+        let mode_button = |label, mode| {
+            let color = if comparison.mode == mode {
+                Color::from_rgb8(0xff, 0xf1, 0x00) // Yellow
+            } else {
+                Color::WHITE
+            };
+            button(text(label).size(24).style(theme::Text::Color(color)))
+                .on_press(Message::ChangeCompareMode(mode))
+                .style(theme::Button::Text)
+        };
+        column![
+            row![
+                text(time(before)).size(24),
+                Space::with_width(Length::Fill),
+                mode_button("Split", CompareMode::SideBySide),
+                mode_button("Wipe", CompareMode::Wipe),
+                button(text("Close").size(24))
+                    .on_press(Message::StopComparing)
+                    .style(theme::Button::Text),
+                Space::with_width(Length::Fill),
+                text(time(after)).size(24),
+            ]
+            .spacing(20)
+            .padding(10)
+            .align_items(Alignment::Center),
+            match &comparison.handles {
+                Some((before, after)) => {
+                    Element::from(Compare::new(before.clone(), after.clone(), comparison.mode))
+                }
+                None => container(
+                    text("Loading…")
+                        .size(30)
+                        .style(theme::Text::Color(Color::from_rgb8(128, 128, 128))),
                 )
-                .spacing(10),
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .center_x()
+                .center_y()
+                .into(),
+            },
+        ]
+        .into()
+    }
+
+    fn menu(&self) -> Element<'_, Message> {
+        let current_id = self.current_image.as_ref().map(|(id, _)| *id);
+        let indexing = self.is_indexing.then(|| {
+            container(
+                text(format!("Indexing… ({})", self.images.len()))
+                    .size(30)
+                    .style(theme::Text::Color(Color::from_rgb8(128, 128, 128))),
             )
-            .width(Length::Fill)
-            .direction(scrollable::Direction::Vertical(
-                scrollable::Properties::new().width(50).scroller_width(50),
-            ))
-            .height(300);
+            .padding(5)
+            .into()
+        });
+        let progress = Column::with_children(
+            self.download
+                .iter()
+                .map(DownloadingImage::view)
+                .chain(indexing)
+                .collect(),
+        )
+        .spacing(10);
 
         container(
             column![
                 text("HIMAWARI 9").size(44),
-                images,
-                button(text("Close").size(30))
-                    .on_press(Message::HideMenu)
-                    .style(theme::Button::Text),
+                progress,
+                self.source_selector(),
+                self.compare_buttons(),
+                self.gallery
+                    .view(&self.images, &self.thumbnails, current_id),
+                row![
+                    self.gallery.toggle_button(&self.images),
+                    button(text("Close").size(30))
+                        .on_press(Message::HideMenu)
+                        .style(theme::Button::Text),
+                ]
+                .spacing(60),
             ]
             .align_items(Alignment::Center)
             .spacing(30),
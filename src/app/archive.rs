@@ -0,0 +1,829 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Days, NaiveDate, NaiveDateTime, Utc};
+use iced::{subscription, Subscription};
+use image::DynamicImage;
+use tokio::{fs::ReadDir, io::AsyncWriteExt};
+
+use crate::{
+    config::{Layout, StorageConfig},
+    himawari::{DownloadId, Tiles},
+    region,
+};
+
+use self::{index::Record, metadata::Metadata};
+
+use super::downloaded_image::{DownloadedImage, Source};
+
+pub mod bundle;
+pub mod dedupe;
+mod format;
+mod index;
+pub mod metadata;
+pub mod thumbnail;
+
+pub const IMAGE_DIR: &str = "./images";
+/// 最後に保存した画像のパスを記録するファイル
+const LATEST_FILE_NAME: &str = "latest";
+/// 読み込めなかった画像を移動するディレクトリ
+const QUARANTINE_DIR_NAME: &str = "quarantine";
+/// 一度に`App`に渡す画像の数
+const BATCH_SIZE: usize = 256;
+
+/// 画像の保存先
+///
+/// 全球画像から派生した画像は`<timestamp>.<product>.<extension>`として並べて保存する。
+pub fn image_path(
+    id: DownloadId,
+    product: Option<&str>,
+    layout: Layout,
+    extension: &str,
+) -> PathBuf {
+    let datetime = id.as_utc_datetime();
+    let (dir, timestamp) = match layout {
+        Layout::Flat => (PathBuf::from(IMAGE_DIR), datetime.format("%Y%m%d%H%M%S")),
+        Layout::Dated => (
+            Path::new(IMAGE_DIR).join(datetime.format("%Y/%m/%d").to_string()),
+            datetime.format("%H%M"),
+        ),
+    };
+    match product {
+        Some(product) => dir.join(format!("{timestamp}.{product}.{extension}")),
+        None => dir.join(format!("{timestamp}.{extension}")),
+    }
+}
+
+/// 画像を設定された形式で保存し、索引に記録する
+pub async fn save(
+    image: &DynamicImage,
+    metadata: &Metadata,
+    tiles: Option<&Tiles>,
+    storage: &StorageConfig,
+) -> anyhow::Result<PathBuf> {
+    let (id, product) = (metadata.id, metadata.product.as_deref());
+    let path = image_path(id, product, storage.layout, storage.format.extension());
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let data = format::encode(image, storage.format, metadata)?;
+    write_atomic(&path, &data).await?;
+
+    let mut record = Record::new(id, &path, product, &data, tiles);
+    record.set_metadata(metadata);
+    index::append(&[record]).await?;
+    Ok(path)
+}
+
+/// 保存済みの画像をすべて設定された形式で保存し直す。保存し直した画像の数を返す
+///
+/// すでに設定された形式の画像は、非可逆な形式で圧縮し直して劣化しないようにそのままにする。
+/// 新しい画像を索引に追加してから元の画像を消すので、途中で止まっても画像は失われない。
+/// PNG以外の形式には画像の情報を埋め込めないので、索引に残す。
+pub async fn convert(storage: &StorageConfig) -> anyhow::Result<usize> {
+    let records = index::load().await.unwrap_or_default();
+    let latest = latest();
+    let mut count = 0;
+    for path in image_files().await? {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if extension.is_some_and(|extension| storage.format.matches_extension(extension)) {
+            continue;
+        }
+        let data = tokio::fs::read(&path).await?;
+        let Some(mut metadata) = metadata::read(&data).or_else(|| {
+            let (id, product) = parse_file_name(&path)?;
+            Some(Metadata {
+                id,
+                product,
+                level: None,
+                processing: None,
+                quality: None,
+                disk: None,
+            })
+        }) else {
+            continue;
+        };
+        let image = match image::load_from_memory(&data) {
+            Ok(image) => image,
+            Err(e) => {
+                log::warn!("Skip broken image: {}: {e}", path.display());
+                continue;
+            }
+        };
+
+        // ダウンロードの記録と画像の情報は元の画像から引き継ぐ
+        let relative_path = path.strip_prefix(IMAGE_DIR).unwrap_or(&path);
+        let original = records.iter().find(|record| record.path == relative_path);
+        if let Some(original) = original {
+            metadata.quality = metadata.quality.or_else(|| original.quality.clone());
+            metadata.level = metadata.level.or(original.level);
+            metadata.processing = metadata.processing.or_else(|| original.processing.clone());
+            metadata.disk = metadata.disk.or(original.disk);
+        }
+
+        let data = format::encode(&image, storage.format, &metadata)?;
+        let destination = path.with_extension(storage.format.extension());
+        write_atomic(&destination, &data).await?;
+
+        let mut record = Record::new(
+            metadata.id,
+            &destination,
+            metadata.product.as_deref(),
+            &data,
+            None,
+        );
+        record.set_metadata(&metadata);
+        if let Some(original) = original {
+            record.download_duration_ms = original.download_duration_ms;
+            record.source_urls = original.source_urls.clone();
+        }
+        index::append(&[record]).await?;
+        if destination != path {
+            tokio::fs::remove_file(&path).await?;
+        }
+        if latest
+            .as_ref()
+            .is_some_and(|latest| latest.source == Source::File(path.clone()))
+        {
+            let image = DownloadedImage {
+                source: Source::File(destination.clone()),
+                id: metadata.id,
+                product: metadata.product.clone(),
+                quality: metadata.quality.clone(),
+                disk: metadata.disk,
+            };
+            set_latest(&image).await?;
+        }
+        log::info!("Converted: {}", destination.display());
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// `days`日より前の画像を日(UTC)ごとに1つのファイルにまとめる。まとめた全球画像と領域の画像を返す
+///
+/// まとめたファイルを書き終えて索引に記録してから元の画像を消す。
+pub async fn compact(layout: Layout, days: u32) -> anyhow::Result<Vec<DownloadedImage>> {
+    let cutoff = Utc::now().date_naive() - Days::new(days as u64);
+    let mut dates = BTreeMap::<NaiveDate, Vec<Record>>::new();
+    for record in index::load().await? {
+        let date = record.id.as_utc_datetime().date_naive();
+        // 索引から取り除かれる前の消えたファイルは飛ばす
+        if record.member.is_none() && date < cutoff {
+            let is_file = tokio::fs::metadata(record.full_path())
+                .await
+                .is_ok_and(|metadata| metadata.is_file());
+            if is_file {
+                dates.entry(date).or_default().push(record);
+            }
+        }
+    }
+    if dates.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut images = vec![];
+    let mut compacted = HashSet::new();
+    for (date, records) in dates {
+        let path = bundle_path(date, layout);
+        let files = records
+            .into_iter()
+            .map(|record| {
+                let file = record.full_path();
+                (record, file)
+            })
+            .collect::<Vec<_>>();
+        let originals = files
+            .iter()
+            .map(|(_, file)| file.clone())
+            .collect::<Vec<_>>();
+        let bundled = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || bundle::write(&path, files)).await??
+        };
+        index::append(&bundled).await?;
+
+        for original in &originals {
+            tokio::fs::remove_file(original).await?;
+            compacted.insert(
+                original
+                    .strip_prefix(IMAGE_DIR)
+                    .unwrap_or(original)
+                    .to_path_buf(),
+            );
+        }
+        // 空になった日付のディレクトリは消す。まだ画像が残っていれば失敗するので無視する
+        if layout == Layout::Dated {
+            let dir = Path::new(IMAGE_DIR).join(date.format("%Y/%m/%d").to_string());
+            let _ = tokio::fs::remove_dir(dir).await;
+        }
+        log::info!(
+            "Compacted {} images into {}",
+            originals.len(),
+            path.display()
+        );
+
+        images.extend(
+            bundled
+                .iter()
+                .filter(|record| is_displayed(record))
+                .map(Record::image),
+        );
+    }
+
+    // まとめた画像の元の記録を索引から取り除く
+    index::update(|records| {
+        records.retain(|record| record.member.is_some() || !compacted.contains(&record.path));
+    })
+    .await?;
+    Ok(images)
+}
+
+/// 1日分の画像をまとめたファイルのパス
+fn bundle_path(date: NaiveDate, layout: Layout) -> PathBuf {
+    let extension = bundle::EXTENSION;
+    match layout {
+        Layout::Flat => Path::new(IMAGE_DIR).join(format!("{}.{extension}", date.format("%Y%m%d"))),
+        Layout::Dated => {
+            Path::new(IMAGE_DIR).join(format!("{}.{extension}", date.format("%Y/%m/%d")))
+        }
+    }
+}
+
+/// `IMAGE_DIR`以下の画像のパス。隔離した画像とサムネイルは含まない
+async fn image_files() -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut directories = vec![PathBuf::from(IMAGE_DIR)];
+    while let Some(dir) = directories.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                if !is_special_dir(&path) {
+                    directories.push(path);
+                }
+            } else if is_image_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// 隔離した画像やサムネイルを置くディレクトリか
+fn is_special_dir(path: &Path) -> bool {
+    [QUARANTINE_DIR_NAME, thumbnail::DIR_NAME]
+        .iter()
+        .any(|name| path == Path::new(IMAGE_DIR).join(name))
+}
+
+/// 画面に表示する画像か。全球画像と領域の画像を表示する
+fn is_displayed(record: &Record) -> bool {
+    record
+        .product
+        .as_deref()
+        .is_none_or(region::is_region_product)
+}
+
+fn is_bundle_file(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.ends_with(&format!(".{}", bundle::EXTENSION)))
+}
+
+async fn read_bundle_index(path: &Path) -> anyhow::Result<Vec<Record>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || bundle::read_index(&path)).await?
+}
+
+fn is_image_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| format::EXTENSIONS.contains(&extension))
+}
+
+/// 最後に保存した画像
+///
+/// ディレクトリ全体を読まなくても起動直後に表示できるように、保存のたびに記録しておく。
+pub fn latest() -> Option<DownloadedImage> {
+    let relative_path = fs::read_to_string(Path::new(IMAGE_DIR).join(LATEST_FILE_NAME)).ok()?;
+    let path = Path::new(IMAGE_DIR).join(relative_path.trim());
+    if !path.is_file() {
+        return None;
+    }
+    full_disk_image(path)
+}
+
+/// 全球画像のファイルを撮影時刻とともに返す。全球画像でなければ`None`
+///
+/// 名前を変えられても分かるように、埋め込まれた情報をファイル名より優先する。
+fn full_disk_image(path: PathBuf) -> Option<DownloadedImage> {
+    let metadata = metadata::read_file(&path).or_else(|| {
+        let (id, product) = parse_file_name(&path)?;
+        Some(Metadata {
+            id,
+            product,
+            level: None,
+            processing: None,
+            quality: None,
+            disk: None,
+        })
+    })?;
+    metadata.product.is_none().then_some(DownloadedImage {
+        source: Source::File(path),
+        id: metadata.id,
+        product: None,
+        quality: metadata.quality,
+        disk: metadata.disk,
+    })
+}
+
+pub async fn set_latest(image: &DownloadedImage) -> anyhow::Result<()> {
+    let Source::File(path) = &image.source else {
+        return Ok(());
+    };
+    let relative_path = path.strip_prefix(IMAGE_DIR).unwrap_or(path);
+    if let Some(relative_path) = relative_path.to_str() {
+        write_atomic(
+            &Path::new(IMAGE_DIR).join(LATEST_FILE_NAME),
+            relative_path.as_bytes(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// 一時ファイルに書き込んでfsyncしてから置き換える
+///
+/// 書き込み中に電源が切れても、途中までしか書かれていないファイルが残らないようにする。
+async fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let temporary_path = temporary_path(path);
+    let mut file = tokio::fs::File::create(&temporary_path).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&temporary_path, path).await?;
+
+    // リネームも確実に書き込まれるようにディレクトリもfsyncする。対応していない環境もあるので失敗は無視する
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = tokio::fs::File::open(dir).await {
+            let _ = dir.sync_all().await;
+        }
+    }
+    Ok(())
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+/// ファイルを移す。移動先のディレクトリがなければ作る
+async fn move_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if let Some(dir) = to.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    tokio::fs::rename(from, to).await?;
+    Ok(())
+}
+
+/// 移した画像の索引と最後に保存した画像の記録を新しいパスに書き換える
+async fn migrate_index(moved: &HashMap<PathBuf, PathBuf>) {
+    let updated = index::update(|records| {
+        for record in records {
+            if let Some(path) = moved.get(&record.path) {
+                record.path = path.clone();
+            }
+        }
+    });
+    // 索引がなければこの後作り直す
+    if let Err(e) = updated.await {
+        log::warn!("failed to rewrite index: {e:#}");
+    }
+    if let Some(image) = latest_in(moved) {
+        if let Err(e) = set_latest(&image).await {
+            log::error!("failed to update {LATEST_FILE_NAME}: {e}");
+        }
+    }
+}
+
+/// 移した画像の中で、最後に保存した画像として記録されていたもの
+fn latest_in(moved: &HashMap<PathBuf, PathBuf>) -> Option<DownloadedImage> {
+    let relative_path = fs::read_to_string(Path::new(IMAGE_DIR).join(LATEST_FILE_NAME)).ok()?;
+    let path = Path::new(IMAGE_DIR).join(moved.get(Path::new(relative_path.trim()))?);
+    full_disk_image(path)
+}
+
+/// 画像として読み込めるか確かめる
+async fn verify(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let data = tokio::fs::read(path).await?;
+    tokio::task::spawn_blocking(move || {
+        image::load_from_memory(&data)?;
+        Ok(data)
+    })
+    .await?
+}
+
+/// 読み込めない画像を`IMAGE_DIR/quarantine`の同じ相対パスに移す
+async fn quarantine(path: &Path) -> anyhow::Result<()> {
+    let relative_path = path.strip_prefix(IMAGE_DIR).unwrap_or(path);
+    let destination = Path::new(IMAGE_DIR)
+        .join(QUARANTINE_DIR_NAME)
+        .join(relative_path);
+    if let Some(dir) = destination.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    tokio::fs::rename(path, destination).await?;
+    Ok(())
+}
+
+/// パスから撮影時刻と派生した画像の種類を読み取る。画像でないファイルは`None`になる
+///
+/// `YYYYmmddHHMMSS.png`と`YYYY/mm/dd/HHMM.png`のどちらも読める。拡張子は`format::EXTENSIONS`のどれでもよい
+fn parse_file_name(path: &Path) -> Option<(DownloadId, Option<String>)> {
+    if !is_image_file(path) {
+        return None;
+    }
+    let file_name = path.file_name()?.to_str()?;
+    let stem = path.file_stem()?.to_str()?;
+    let (timestamp, product) = match stem.split_once('.') {
+        Some((timestamp, product)) => (timestamp, Some(product.to_string())),
+        None => (stem, None),
+    };
+    let timestamp = match timestamp.len() {
+        // 日付はディレクトリから読む
+        4 => {
+            let mut dirs = path.parent()?.iter().rev().filter_map(|dir| dir.to_str());
+            let (day, month, year) = (dirs.next()?, dirs.next()?, dirs.next()?);
+            NaiveDateTime::parse_from_str(&format!("{year}{month}{day}{timestamp}"), "%Y%m%d%H%M")
+        }
+        _ => NaiveDateTime::parse_from_str(timestamp, "%Y%m%d%H%M%S"),
+    };
+    let Ok(timestamp) = timestamp else {
+        log::warn!("unexpected filename: {file_name}");
+        return None;
+    };
+    Some((DownloadId::new(timestamp.and_utc()), product))
+}
+
+#[derive(Debug, Clone)]
+pub enum IndexProgress {
+    /// 見つかった全球画像と領域の画像。順序は保証されない
+    Indexed {
+        images: Vec<DownloadedImage>,
+        /// 読み込めずに隔離した画像。ダウンロードし直す
+        broken: Vec<DownloadId>,
+    },
+    Finished {
+        /// 最近重複として保存しなかった画像。ダウンロードし直さない
+        skipped: HashSet<DownloadId>,
+    },
+}
+
+impl IndexProgress {
+    fn indexed(images: Vec<DownloadedImage>, broken: Vec<DownloadId>) -> Self {
+        IndexProgress::Indexed { images, broken }
+    }
+
+    async fn finished() -> Self {
+        IndexProgress::Finished {
+            skipped: dedupe::recently_skipped().await,
+        }
+    }
+}
+
+/// 保存済みの画像を少しずつ読み取って通知する
+///
+/// 索引があればそれを使い、なければ`IMAGE_DIR`を走査して索引を作り直す。
+/// 読み込めない画像は隔離して索引から取り除く。
+/// `layout`が`Dated`なら、先に`IMAGE_DIR`の直下に並んでいる画像を日付ごとのディレクトリに移す。
+/// 移し終えたことを記録しておき、次からは走査しない。
+pub fn index_subscription(layout: Layout) -> Subscription<IndexProgress> {
+    subscription::unfold("index", State::Ready(layout), index)
+}
+
+async fn index(state: State) -> (IndexProgress, State) {
+    match state {
+        State::Ready(Layout::Flat) => {
+            if let Err(e) = index::set_migrated(false).await {
+                log::error!("failed to reset migration: {e}");
+            }
+            (IndexProgress::indexed(vec![], vec![]), State::Opening)
+        }
+        State::Ready(Layout::Dated) if index::is_migrated().await => {
+            (IndexProgress::indexed(vec![], vec![]), State::Opening)
+        }
+        State::Ready(Layout::Dated) => match tokio::fs::read_dir(IMAGE_DIR).await {
+            Ok(entries) => {
+                let state = State::Migrating {
+                    entries,
+                    moved: HashMap::new(),
+                    failed: false,
+                };
+                (IndexProgress::indexed(vec![], vec![]), state)
+            }
+            Err(_) => (IndexProgress::indexed(vec![], vec![]), State::Opening),
+        },
+        State::Migrating {
+            mut entries,
+            mut moved,
+            mut failed,
+        } => {
+            let mut count = 0;
+            while count < BATCH_SIZE {
+                let path = match entries.next_entry().await {
+                    Ok(Some(entry)) => entry.path(),
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::error!("failed to read {IMAGE_DIR}: {e}");
+                        failed = true;
+                        break;
+                    }
+                };
+                let Some((id, product)) = parse_file_name(&path) else {
+                    continue;
+                };
+                let extension = path.extension().and_then(|extension| extension.to_str());
+                let destination =
+                    image_path(id, product.as_deref(), Layout::Dated, extension.unwrap());
+                if let Err(e) = move_file(&path, &destination).await {
+                    log::error!("failed to move {}: {e}", path.display());
+                    failed = true;
+                    continue;
+                }
+                let relative_path =
+                    |path: &Path| path.strip_prefix(IMAGE_DIR).unwrap().to_path_buf();
+                moved.insert(relative_path(&path), relative_path(&destination));
+                count += 1;
+            }
+            if count > 0 {
+                let state = State::Migrating {
+                    entries,
+                    moved,
+                    failed,
+                };
+                return (IndexProgress::indexed(vec![], vec![]), state);
+            }
+            if !moved.is_empty() {
+                log::info!("Migrated {} images to dated directories", moved.len());
+                migrate_index(&moved).await;
+            }
+            // 移せなかった画像があれば次の起動時にもう一度試す
+            if !failed {
+                if let Err(e) = index::set_migrated(true).await {
+                    log::error!("failed to record migration: {e}");
+                }
+            }
+            (IndexProgress::indexed(vec![], vec![]), State::Opening)
+        }
+        State::Opening => match index::load().await {
+            Ok(records) => {
+                log::info!("Load index: {} records", records.len());
+                let state = State::Loading {
+                    records: records.into_iter(),
+                    pruned: HashSet::new(),
+                    replaced: HashMap::new(),
+                };
+                (IndexProgress::indexed(vec![], vec![]), state)
+            }
+            Err(e) => {
+                log::warn!("Rebuild index: {e:#}");
+                if let Err(e) = index::clear().await {
+                    log::error!("failed to clear index: {e}");
+                }
+                match tokio::fs::read_dir(IMAGE_DIR).await {
+                    Ok(entries) => {
+                        let state = State::Scanning {
+                            entries,
+                            directories: vec![],
+                        };
+                        (IndexProgress::indexed(vec![], vec![]), state)
+                    }
+                    Err(e) => {
+                        log::error!("{e}");
+                        (IndexProgress::finished().await, State::Finished)
+                    }
+                }
+            }
+        },
+        State::Loading {
+            mut records,
+            mut pruned,
+            mut replaced,
+        } => {
+            let mut images = Vec::with_capacity(BATCH_SIZE);
+            let mut broken = vec![];
+            for mut record in records.by_ref().take(BATCH_SIZE) {
+                // 消えたファイルは索引から取り除く
+                let path = record.full_path();
+                let Ok(metadata) = tokio::fs::metadata(&path).await else {
+                    log::info!("Prune missing image: {}", path.display());
+                    pruned.insert(record.key());
+                    continue;
+                };
+                // 大きさが記録と違うファイルは書き込みが途中で止まったかもしれないので確かめる
+                if record.member.is_none() && metadata.len() != record.size {
+                    match verify(&path).await {
+                        Ok(data) => {
+                            record = Record::new(
+                                record.id,
+                                &path,
+                                record.product.as_deref(),
+                                &data,
+                                None,
+                            );
+                            if let Some(metadata) = metadata::read(&data) {
+                                record.set_metadata(&metadata);
+                            }
+                            replaced.insert(record.key(), record.clone());
+                        }
+                        Err(e) => {
+                            log::warn!("Broken image: {}: {e}", path.display());
+                            if let Err(e) = quarantine(&path).await {
+                                log::error!("failed to quarantine {}: {e}", path.display());
+                            }
+                            if record.product.is_none() {
+                                broken.push(record.id);
+                            }
+                            pruned.insert(record.key());
+                            continue;
+                        }
+                    }
+                }
+                if is_displayed(&record) {
+                    images.push(record.image());
+                }
+            }
+            if !records.as_slice().is_empty() || !images.is_empty() || !broken.is_empty() {
+                let state = State::Loading {
+                    records,
+                    pruned,
+                    replaced,
+                };
+                return (IndexProgress::indexed(images, broken), state);
+            }
+            if !pruned.is_empty() || !replaced.is_empty() {
+                // 読み込んでいる間に保存された画像の記録は残す
+                let updated = index::update(|records| {
+                    records.retain(|record| !pruned.contains(&record.key()));
+                    for record in records {
+                        if let Some(new) = replaced.remove(&record.key()) {
+                            *record = new;
+                        }
+                    }
+                });
+                if let Err(e) = updated.await {
+                    log::error!("failed to rewrite index: {e}");
+                }
+            }
+            log::info!("Indexing finished");
+            (IndexProgress::finished().await, State::Finished)
+        }
+        State::Scanning {
+            mut entries,
+            mut directories,
+        } => {
+            let mut images = Vec::with_capacity(BATCH_SIZE);
+            let mut records = Vec::with_capacity(BATCH_SIZE);
+            let mut broken = vec![];
+            while records.len() + broken.len() < BATCH_SIZE {
+                let entry = match entries.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => {
+                        // 見つけたサブディレクトリを順に走査する
+                        let Some(dir) = directories.pop() else {
+                            break;
+                        };
+                        match tokio::fs::read_dir(&dir).await {
+                            Ok(next_entries) => entries = next_entries,
+                            Err(e) => log::error!("failed to read {}: {e}", dir.display()),
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("failed to read {IMAGE_DIR}: {e}");
+                        break;
+                    }
+                };
+                let path = entry.path();
+                if entry
+                    .file_type()
+                    .await
+                    .is_ok_and(|file_type| file_type.is_dir())
+                {
+                    if !is_special_dir(&path) {
+                        directories.push(path);
+                    }
+                    continue;
+                }
+                // 書き込み中に止まった一時ファイルは消す
+                if path.extension().is_some_and(|extension| extension == "tmp") {
+                    log::info!("Remove temporary file: {}", path.display());
+                    if let Err(e) = tokio::fs::remove_file(&path).await {
+                        log::error!("failed to remove {}: {e}", path.display());
+                    }
+                    continue;
+                }
+                if is_bundle_file(&path) {
+                    match read_bundle_index(&path).await {
+                        Ok(bundled) => {
+                            images.extend(
+                                bundled
+                                    .iter()
+                                    .filter(|record| is_displayed(record))
+                                    .map(Record::image),
+                            );
+                            records.extend(bundled);
+                        }
+                        Err(e) => log::error!("failed to read {}: {e}", path.display()),
+                    }
+                    continue;
+                }
+                if !is_image_file(&path) {
+                    continue;
+                }
+                match verify(&path).await {
+                    Ok(data) => {
+                        // 名前を変えられても分かるように、埋め込まれた情報を優先する
+                        let record = match metadata::read(&data) {
+                            Some(metadata) => {
+                                let product = metadata.product.as_deref();
+                                let mut record =
+                                    Record::new(metadata.id, &path, product, &data, None);
+                                record.set_metadata(&metadata);
+                                record
+                            }
+                            None => {
+                                let Some((id, product)) = parse_file_name(&path) else {
+                                    continue;
+                                };
+                                Record::new(id, &path, product.as_deref(), &data, None)
+                            }
+                        };
+                        if is_displayed(&record) {
+                            images.push(record.image());
+                        }
+                        records.push(record);
+                    }
+                    Err(e) => {
+                        log::warn!("Broken image: {}: {e}", path.display());
+                        if let Err(e) = quarantine(&path).await {
+                            log::error!("failed to quarantine {}: {e}", path.display());
+                        }
+                        if let Some((id, None)) = parse_file_name(&path) {
+                            broken.push(id);
+                        }
+                    }
+                }
+            }
+            if records.is_empty() && broken.is_empty() {
+                log::info!("Indexing finished");
+                return (IndexProgress::finished().await, State::Finished);
+            }
+            if let Err(e) = index::append(&records).await {
+                log::error!("failed to write index: {e}");
+            }
+            let state = State::Scanning {
+                entries,
+                directories,
+            };
+            (IndexProgress::indexed(images, broken), state)
+        }
+        State::Finished => {
+            // ここで停止
+            iced::futures::future::pending().await
+        }
+    }
+}
+
+enum State {
+    Ready(Layout),
+    /// `IMAGE_DIR`の直下の画像を日付ごとのディレクトリに移している
+    Migrating {
+        entries: ReadDir,
+        /// 移した画像の`IMAGE_DIR`からの相対パス
+        moved: HashMap<PathBuf, PathBuf>,
+        /// 移せなかった画像があった
+        failed: bool,
+    },
+    Opening,
+    /// 索引から読み込んでいる
+    Loading {
+        records: std::vec::IntoIter<Record>,
+        /// 索引から取り除く記録
+        pruned: HashSet<index::Key>,
+        /// 確かめ直して書き換える記録
+        replaced: HashMap<index::Key, Record>,
+    },
+    /// 索引を作り直している
+    Scanning {
+        entries: ReadDir,
+        /// まだ走査していないサブディレクトリ
+        directories: Vec<PathBuf>,
+    },
+    Finished,
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _};
+use serde::{Deserialize, Serialize};
+
+use super::{index::Record, temporary_path, IMAGE_DIR};
+
+/// 1日分の画像をまとめたファイルの拡張子
+pub const EXTENSION: &str = "tar.zst";
+
+/// tarのブロックの大きさ [byte]
+const BLOCK_SIZE: usize = 512;
+/// zstdのスキップ可能フレームのマジックナンバー
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D_2A50;
+/// 末尾の索引を示すマジックナンバー
+const FOOTER_MAGIC: &[u8; 4] = b"HWBI";
+/// zstdの圧縮レベル。PNGなどはすでに圧縮されているので軽くしておく
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// まとめたファイルの中の画像1枚の位置
+///
+/// 画像ごとに独立したzstdのフレームにしているので、そのフレームだけを読めば取り出せる。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    /// tarの中のファイル名
+    pub name: String,
+    /// フレームの位置 [byte]
+    pub offset: u64,
+    /// 圧縮したフレームの大きさ [byte]
+    pub length: u64,
+}
+
+/// 画像を1つのファイルにまとめる
+///
+/// 中身は画像ごとにzstdのフレームを分けたtarで、`zstd -d`と`tar`でそのまま展開できる。
+/// 末尾のスキップ可能フレームに`Record`の一覧を索引として書き込む。
+/// `path`がすでにあれば、その中の画像も引き継ぐ。
+pub fn write(path: &Path, files: Vec<(Record, PathBuf)>) -> anyhow::Result<Vec<Record>> {
+    let temporary_path = temporary_path(path);
+    let result = write_to(path, &temporary_path, files);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+fn write_to(
+    path: &Path,
+    temporary_path: &Path,
+    files: Vec<(Record, PathBuf)>,
+) -> anyhow::Result<Vec<Record>> {
+    let existing = if path.exists() {
+        Some((File::open(path)?, read_index(path)?))
+    } else {
+        None
+    };
+
+    let relative_path = path.strip_prefix(IMAGE_DIR).unwrap_or(path);
+    let mut output = File::create(temporary_path)?;
+    let mut records = vec![];
+    let mut offset = 0;
+
+    // 既存の画像は圧縮したフレームをそのまま写す
+    if let Some((mut input, existing_records)) = existing {
+        for mut record in existing_records {
+            let Some(member) = record.member.as_mut() else {
+                continue;
+            };
+            // 同じ名前の画像は新しいほうを使う
+            if files
+                .iter()
+                .any(|(_, file)| file.file_name().is_some_and(|name| *name == *member.name))
+            {
+                continue;
+            }
+            let mut frame = vec![0; member.length as usize];
+            input.seek(SeekFrom::Start(member.offset))?;
+            input.read_exact(&mut frame)?;
+            output.write_all(&frame)?;
+            member.offset = offset;
+            offset += member.length;
+            records.push(record);
+        }
+    }
+
+    for (mut record, file) in files {
+        let data =
+            std::fs::read(&file).with_context(|| format!("failed to read {}", file.display()))?;
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("invalid file name")?
+            .to_string();
+
+        let mut header = tar::Header::new_ustar();
+        header.set_path(&name)?;
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(record.id.as_utc_datetime().timestamp().max(0) as u64);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+
+        let mut entry = header.as_bytes().to_vec();
+        entry.extend_from_slice(&data);
+        entry.resize(entry.len().next_multiple_of(BLOCK_SIZE), 0);
+        let frame = zstd::bulk::compress(&entry, COMPRESSION_LEVEL)?;
+        output.write_all(&frame)?;
+
+        record.path = relative_path.to_path_buf();
+        record.member = Some(Member {
+            name,
+            offset,
+            length: frame.len() as u64,
+        });
+        offset += frame.len() as u64;
+        records.push(record);
+    }
+
+    // tarの終わりを示す空のブロック
+    output.write_all(&zstd::bulk::compress(
+        &[0; BLOCK_SIZE * 2],
+        COMPRESSION_LEVEL,
+    )?)?;
+
+    let mut index = serde_json::to_vec(&records)?;
+    index.extend_from_slice(&(index.len() as u32).to_le_bytes());
+    index.extend_from_slice(FOOTER_MAGIC);
+    output.write_all(&SKIPPABLE_FRAME_MAGIC.to_le_bytes())?;
+    output.write_all(&(index.len() as u32).to_le_bytes())?;
+    output.write_all(&index)?;
+
+    output.sync_all()?;
+    drop(output);
+    std::fs::rename(temporary_path, path)?;
+    Ok(records)
+}
+
+/// まとめたファイルの末尾の索引を読む
+pub fn read_index(path: &Path) -> anyhow::Result<Vec<Record>> {
+    let mut file = File::open(path)?;
+    let mut footer = [0; 8];
+    file.seek(SeekFrom::End(-(footer.len() as i64)))?;
+    file.read_exact(&mut footer)?;
+    let (length, magic) = footer.split_at(4);
+    if magic != FOOTER_MAGIC {
+        bail!("{} has no index", path.display());
+    }
+
+    let length = u32::from_le_bytes(length.try_into()?) as i64;
+    let mut index = vec![0; length as usize];
+    file.seek(SeekFrom::End(-(footer.len() as i64) - length))?;
+    file.read_exact(&mut index)?;
+
+    // ファイルが移されていても読めるように、パスは今の場所にする
+    let relative_path = path.strip_prefix(IMAGE_DIR).unwrap_or(path);
+    let mut records = serde_json::from_slice::<Vec<Record>>(&index)?;
+    for record in &mut records {
+        record.path = relative_path.to_path_buf();
+    }
+    Ok(records)
+}
+
+/// まとめたファイルから画像1枚を取り出す
+pub fn read(path: &Path, member: &Member) -> anyhow::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut frame = vec![0; member.length as usize];
+    file.seek(SeekFrom::Start(member.offset))?;
+    file.read_exact(&mut frame)?;
+
+    let entry = zstd::stream::decode_all(frame.as_slice())?;
+    if entry.len() < BLOCK_SIZE {
+        bail!("{} in {} is truncated", member.name, path.display());
+    }
+    let header = tar::Header::from_byte_slice(&entry[..BLOCK_SIZE]);
+    let size = header.entry_size()? as usize;
+    entry
+        .get(BLOCK_SIZE..BLOCK_SIZE + size)
+        .map(<[u8]>::to_vec)
+        .with_context(|| format!("{} in {} is truncated", member.name, path.display()))
+}
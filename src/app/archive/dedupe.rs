@@ -0,0 +1,215 @@
+use std::{
+    collections::HashSet,
+    f64::consts::SQRT_2,
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use image::{imageops, DynamicImage, RgbImage};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::{DedupeConfig, DedupeMode},
+    himawari::{DownloadId, FullDisk},
+};
+
+use super::IMAGE_DIR;
+
+/// 重複の判定を1行に1件ずつ記録するファイル
+const LOG_FILE_NAME: &str = "dedupe.jsonl";
+/// 直前の判定を探すときに読む末尾の大きさ [byte]
+const TAIL_SIZE: u64 = 4096;
+/// ハッシュを計算するときの縮小後の大きさ [px]
+const HASH_SIZE: u32 = 16;
+
+/// 256ビットのdifference hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct ImageHash([u64; 4]);
+
+impl ImageHash {
+    /// 全球画像のハッシュ
+    ///
+    /// 毎回同じ形をしている地球の縁の影響を受けないように、地球に内接する正方形だけを見る。
+    pub fn new(disk: &RgbImage) -> Self {
+        let size = disk.width();
+        let side = (FullDisk::new(size).limb_radius() * SQRT_2) as u32;
+        let offset = (size - side) / 2;
+        let inner = imageops::crop_imm(disk, offset, offset, side, side).to_image();
+        let gray = DynamicImage::ImageRgb8(inner).into_luma8();
+        let small = imageops::resize(
+            &gray,
+            HASH_SIZE + 1,
+            HASH_SIZE,
+            imageops::FilterType::Triangle,
+        );
+
+        // 横に隣り合う画素の明るさの大小を1ビットずつ並べる
+        let mut bits = [0; 4];
+        for y in 0..HASH_SIZE {
+            for x in 0..HASH_SIZE {
+                if small.get_pixel(x + 1, y)[0] > small.get_pixel(x, y)[0] {
+                    let i = (y * HASH_SIZE + x) as usize;
+                    bits[i / 64] |= 1 << (i % 64);
+                }
+            }
+        }
+        ImageHash(bits)
+    }
+
+    /// 異なるビットの数
+    pub fn distance(&self, other: &ImageHash) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+impl fmt::Display for ImageHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for word in self.0 {
+            write!(f, "{word:016x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ImageHash> for String {
+    fn from(hash: ImageHash) -> Self {
+        hash.to_string()
+    }
+}
+
+impl TryFrom<String> for ImageHash {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        anyhow::ensure!(s.len() == 64 && s.is_ascii(), "invalid hash: {s}");
+        let mut words = [0; 4];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = u64::from_str_radix(&s[i * 16..(i + 1) * 16], 16)?;
+        }
+        Ok(ImageHash(words))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Unique,
+    /// 直前の画像とほぼ同じだが保存した
+    Flagged,
+    /// 直前の画像とほぼ同じなので保存しなかった
+    Skipped,
+}
+
+/// 重複の判定の記録
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    id: DownloadId,
+    hash: ImageHash,
+    /// 比べた直前の画像
+    previous: Option<DownloadId>,
+    distance: Option<u32>,
+    decision: Decision,
+}
+
+fn log_path() -> PathBuf {
+    Path::new(IMAGE_DIR).join(LOG_FILE_NAME)
+}
+
+/// 直前の画像と比べて重複しているか判定し、記録する
+pub async fn check(
+    id: DownloadId,
+    disk: &RgbImage,
+    config: &DedupeConfig,
+) -> anyhow::Result<Decision> {
+    if config.mode == DedupeMode::Off {
+        return Ok(Decision::Unique);
+    }
+
+    let hash = ImageHash::new(disk);
+    // 取り直した古い画像は直前の画像と比べられないので重複とはみなさない
+    let previous = tokio::task::spawn_blocking(recent_entries)
+        .await?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.decision != Decision::Skipped)
+        .filter(|entry| entry.id < id);
+    let distance = previous.as_ref().map(|entry| hash.distance(&entry.hash));
+    let decision = match distance {
+        Some(distance) if distance <= config.threshold => match config.mode {
+            DedupeMode::Skip => Decision::Skipped,
+            _ => Decision::Flagged,
+        },
+        _ => Decision::Unique,
+    };
+    if decision != Decision::Unique {
+        log::info!("Duplicate frame: {id:?} (distance {distance:?}, {decision:?})");
+    }
+
+    let entry = Entry {
+        id,
+        hash,
+        previous: previous.map(|entry| entry.id),
+        distance,
+        decision,
+    };
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    tokio::fs::create_dir_all(IMAGE_DIR).await?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    Ok(decision)
+}
+
+/// 記録の末尾の数件のうち、最後の判定で重複として保存しなかった画像
+///
+/// 同じ画像を何度もダウンロードしないために、起動時に読み込んでおく。
+pub async fn recently_skipped() -> HashSet<DownloadId> {
+    let entries = tokio::task::spawn_blocking(recent_entries)
+        .await
+        .unwrap_or_default();
+    let mut skipped = HashSet::new();
+    for entry in entries {
+        if entry.decision == Decision::Skipped {
+            skipped.insert(entry.id);
+        } else {
+            skipped.remove(&entry.id);
+        }
+    }
+    skipped
+}
+
+/// 記録の末尾の数件
+fn recent_entries() -> Vec<Entry> {
+    let Ok(mut file) = File::open(log_path()) else {
+        return vec![];
+    };
+    let start = file
+        .metadata()
+        .map_or(0, |metadata| metadata.len().saturating_sub(TAIL_SIZE));
+    let mut tail = String::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_string(&mut tail).is_err() {
+        return vec![];
+    }
+    // 途中から読んだ最初の行は捨てる
+    let lines = if start > 0 {
+        tail.split_once('\n').map_or("", |(_, rest)| rest)
+    } else {
+        &tail
+    };
+    lines
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
@@ -0,0 +1,78 @@
+use std::io::Cursor;
+
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        webp::{WebPEncoder, WebPQuality},
+    },
+    ColorType, DynamicImage,
+};
+
+use crate::config::StorageFormat;
+
+use super::metadata::{self, Metadata};
+
+/// 読み込める画像の拡張子
+pub const EXTENSIONS: [&str; 4] = ["png", "webp", "jpg", "jpeg"];
+
+impl StorageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StorageFormat::Png { .. } => "png",
+            StorageFormat::WebpLossless | StorageFormat::Webp { .. } => "webp",
+            StorageFormat::Jpeg { .. } => "jpg",
+        }
+    }
+
+    /// `extension`の画像がこの形式で保存されているか
+    pub fn matches_extension(&self, extension: &str) -> bool {
+        let extension = extension.to_ascii_lowercase();
+        match self {
+            StorageFormat::Jpeg { .. } => extension == "jpg" || extension == "jpeg",
+            _ => extension == self.extension(),
+        }
+    }
+}
+
+/// 画像を`format`で符号化する。画像の情報を埋め込めるのはPNGだけ
+pub fn encode(
+    image: &DynamicImage,
+    format: StorageFormat,
+    metadata: &Metadata,
+) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    match format {
+        StorageFormat::Png { compression } => {
+            data = metadata::encode_png(image, metadata, compression)?;
+        }
+        StorageFormat::WebpLossless => encode_webp(image, WebPQuality::lossless(), &mut data)?,
+        StorageFormat::Webp { quality } => {
+            encode_webp(image, WebPQuality::lossy(quality), &mut data)?;
+        }
+        StorageFormat::Jpeg { quality } => {
+            // JPEGは透明を扱えないので捨てる
+            let image = image.to_rgb8();
+            JpegEncoder::new_with_quality(&mut Cursor::new(&mut data), quality)
+                .encode_image(&image)?;
+        }
+    }
+    Ok(data)
+}
+
+fn encode_webp(
+    image: &DynamicImage,
+    quality: WebPQuality,
+    data: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    let encoder = WebPEncoder::new_with_quality(data, quality);
+    match image {
+        DynamicImage::ImageRgb8(image) => {
+            encoder.encode(image, image.width(), image.height(), ColorType::Rgb8)?;
+        }
+        image => {
+            let image = image.to_rgba8();
+            encoder.encode(&image, image.width(), image.height(), ColorType::Rgba8)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{
+    app::downloaded_image::{DownloadedImage, Source},
+    framing::DiskGeometry,
+    himawari::{DownloadId, Tiles},
+    quality::Assessment,
+};
+
+use super::{bundle::Member, metadata::Metadata, IMAGE_DIR};
+
+/// 保存した画像の情報を1行に1件ずつ記録するファイル
+const INDEX_FILE_NAME: &str = "index.jsonl";
+/// 日付ごとのディレクトリへの移動が終わったことを示すファイル
+const MIGRATED_FILE_NAME: &str = "index.migrated";
+
+/// 索引に書き込む処理を1つずつ行うためのロック
+///
+/// 読み込んでから書き直すまでの間に追記された記録が消えないようにする。
+static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// 記録を見分けるためのファイルのパスとまとめたファイルの中の名前
+pub type Key = (PathBuf, Option<String>);
+
+/// 保存した画像1枚分の記録
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub id: DownloadId,
+    /// `IMAGE_DIR`からの相対パス。まとめたファイルに入っている場合はそのファイルのパス
+    pub path: PathBuf,
+    /// まとめたファイルの中の位置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member: Option<Member>,
+    /// 派生した画像の種類。全球画像は`None`
+    pub product: Option<String>,
+    /// ファイルの大きさ [byte]
+    pub size: u64,
+    /// ファイルのSHA-256
+    pub checksum: String,
+    /// タイルのダウンロードにかかった時間 [ms]。索引を作り直した場合は不明
+    pub download_duration_ms: Option<u64>,
+    /// タイルのURL。索引を作り直した場合は不明
+    pub source_urls: Vec<String>,
+    /// 画像の品質。調べていない場合は`None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Assessment>,
+    /// 元にしたタイルのズームレベル。PNG以外の形式では画像に埋め込めないのでここに残す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+    /// 画像処理の設定(JSON)。`level`と同じくここにも残す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processing: Option<String>,
+    /// 画像の中での全球画像の配置。`level`と同じくここにも残す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk: Option<DiskGeometry>,
+}
+
+impl Record {
+    /// 保存したファイルの内容から記録を作る
+    pub fn new(
+        id: DownloadId,
+        path: &Path,
+        product: Option<&str>,
+        data: &[u8],
+        tiles: Option<&Tiles>,
+    ) -> Self {
+        Record {
+            id,
+            path: path.strip_prefix(IMAGE_DIR).unwrap_or(path).to_path_buf(),
+            member: None,
+            product: product.map(str::to_string),
+            size: data.len() as u64,
+            checksum: format!("{:x}", Sha256::digest(data)),
+            download_duration_ms: tiles.map(|tiles| tiles.duration.as_millis() as u64),
+            source_urls: tiles.map_or(vec![], |tiles| tiles.urls.to_vec()),
+            quality: None,
+            level: None,
+            processing: None,
+            disk: None,
+        }
+    }
+
+    /// 画像に埋め込む情報を記録する
+    pub fn set_metadata(&mut self, metadata: &Metadata) {
+        self.quality = metadata.quality.clone();
+        self.level = metadata.level;
+        self.processing = metadata.processing.clone();
+        self.disk = metadata.disk;
+    }
+
+    pub fn key(&self) -> Key {
+        let name = self.member.as_ref().map(|member| member.name.clone());
+        (self.path.clone(), name)
+    }
+
+    /// ファイルのパス
+    pub fn full_path(&self) -> PathBuf {
+        Path::new(IMAGE_DIR).join(&self.path)
+    }
+
+    pub fn image(&self) -> DownloadedImage {
+        DownloadedImage {
+            source: self.source(),
+            id: self.id,
+            product: self.product.clone(),
+            quality: self.quality.clone(),
+            disk: self.disk,
+        }
+    }
+
+    pub fn source(&self) -> Source {
+        match &self.member {
+            Some(member) => Source::Bundled {
+                path: self.full_path(),
+                member: member.clone(),
+            },
+            None => Source::File(self.full_path()),
+        }
+    }
+}
+
+fn index_path() -> PathBuf {
+    Path::new(IMAGE_DIR).join(INDEX_FILE_NAME)
+}
+
+/// 索引を読み込む。索引がない場合や壊れている場合はエラーになる
+///
+/// 同じ画像の記録が複数ある場合は後のものを使う。
+pub async fn load() -> anyhow::Result<Vec<Record>> {
+    let path = index_path();
+    let content = fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    // 追記の途中で止まった最後の行は捨てる
+    let content = match content.rfind('\n') {
+        Some(end) if end + 1 < content.len() => {
+            log::warn!("Discard truncated line in {}", path.display());
+            &content[..end + 1]
+        }
+        None => "",
+        _ => &content,
+    };
+    let mut records = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<Record>(line)
+                .with_context(|| format!("{}:{} is corrupted", path.display(), i + 1))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    records.reverse();
+    let mut seen = std::collections::HashSet::new();
+    records.retain(|record| seen.insert(record.key()));
+    records.reverse();
+    Ok(records)
+}
+
+/// 索引の末尾に記録を追加する
+pub async fn append(records: &[Record]) -> anyhow::Result<()> {
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(&serde_json::to_string(record)?);
+        lines.push('\n');
+    }
+    let _lock = LOCK.lock().await;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index_path())
+        .await?;
+    file.write_all(lines.as_bytes()).await?;
+    Ok(())
+}
+
+/// 索引を空にする
+pub async fn clear() -> anyhow::Result<()> {
+    let _lock = LOCK.lock().await;
+    fs::write(index_path(), "").await?;
+    Ok(())
+}
+
+/// 索引を読み込み直し、`f`で書き換えてから書き直す
+///
+/// 書き直し終わるまで追記を待たせるので、その間に保存した画像の記録も失われない。
+pub async fn update(f: impl FnOnce(&mut Vec<Record>)) -> anyhow::Result<()> {
+    let _lock = LOCK.lock().await;
+    let mut records = load().await?;
+    f(&mut records);
+    rewrite(&records).await
+}
+
+/// `IMAGE_DIR`の直下の画像を日付ごとのディレクトリに移し終えているか
+pub async fn is_migrated() -> bool {
+    fs::try_exists(Path::new(IMAGE_DIR).join(MIGRATED_FILE_NAME))
+        .await
+        .unwrap_or(false)
+}
+
+/// 日付ごとのディレクトリに移し終えたかを記録する
+///
+/// `flat`で保存するとまた直下に画像が並ぶので、そのときは記録を消す。
+pub async fn set_migrated(migrated: bool) -> anyhow::Result<()> {
+    let path = Path::new(IMAGE_DIR).join(MIGRATED_FILE_NAME);
+    if migrated {
+        fs::create_dir_all(IMAGE_DIR).await?;
+        fs::write(path, "").await?;
+    } else if let Err(e) = fs::remove_file(path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// 索引を`records`だけで書き直す
+async fn rewrite(records: &[Record]) -> anyhow::Result<()> {
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(&serde_json::to_string(record)?);
+        lines.push('\n');
+    }
+    super::write_atomic(&index_path(), lines.as_bytes()).await
+}
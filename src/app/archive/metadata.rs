@@ -0,0 +1,137 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, Cursor, Read},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use image::DynamicImage;
+
+use crate::{
+    config::PngCompression, framing::DiskGeometry, himawari::DownloadId, quality::Assessment,
+};
+
+const CAPTURE_TIME_KEY: &str = "Capture Time";
+const SATELLITE_KEY: &str = "Satellite";
+const PRODUCT_KEY: &str = "Product";
+const ZOOM_LEVEL_KEY: &str = "Zoom Level";
+const PROCESSING_KEY: &str = "Processing";
+const SOFTWARE_KEY: &str = "Software";
+const QUALITY_KEY: &str = "Quality";
+const DISK_GEOMETRY_KEY: &str = "Disk Geometry";
+
+const SATELLITE: &str = "Himawari-9";
+/// 全球画像の`Product`の値
+const FULL_DISK_PRODUCT: &str = "full_disk";
+
+/// PNGのテキストチャンクに書き込む画像の情報
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub id: DownloadId,
+    /// 派生した画像の種類。全球画像は`None`
+    pub product: Option<String>,
+    /// 元にしたタイルのズームレベル
+    pub level: Option<u32>,
+    /// 画像処理の設定(JSON)
+    pub processing: Option<String>,
+    pub quality: Option<Assessment>,
+    /// 画像の中での全球画像の配置。全球画像から作った画像だけにある
+    pub disk: Option<DiskGeometry>,
+}
+
+/// 画像を情報つきのPNGにする
+pub fn encode_png(
+    image: &DynamicImage,
+    metadata: &Metadata,
+    compression: PngCompression,
+) -> anyhow::Result<Vec<u8>> {
+    let (color, data) = match image {
+        DynamicImage::ImageRgb8(image) => (png::ColorType::Rgb, Cow::Borrowed(image.as_raw())),
+        DynamicImage::ImageRgba8(image) => (png::ColorType::Rgba, Cow::Borrowed(image.as_raw())),
+        image => (
+            png::ColorType::Rgba,
+            Cow::Owned(image.to_rgba8().into_raw()),
+        ),
+    };
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, image.width(), image.height());
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    match compression {
+        PngCompression::Fast => encoder.set_compression(png::Compression::Fast),
+        PngCompression::Default => encoder.set_compression(png::Compression::Default),
+        PngCompression::Best => {
+            // 行ごとに最適なフィルタを選ぶと遅くなるが小さくなる
+            encoder.set_compression(png::Compression::Best);
+            encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+        }
+    }
+
+    let captured_at = metadata.id.as_utc_datetime().to_rfc3339();
+    let product = metadata.product.as_deref().unwrap_or(FULL_DISK_PRODUCT);
+    let software = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    encoder.add_text_chunk(CAPTURE_TIME_KEY.to_string(), captured_at)?;
+    encoder.add_text_chunk(SATELLITE_KEY.to_string(), SATELLITE.to_string())?;
+    encoder.add_text_chunk(PRODUCT_KEY.to_string(), product.to_string())?;
+    if let Some(level) = metadata.level {
+        encoder.add_text_chunk(ZOOM_LEVEL_KEY.to_string(), level.to_string())?;
+    }
+    encoder.add_text_chunk(SOFTWARE_KEY.to_string(), software)?;
+    if let Some(processing) = &metadata.processing {
+        encoder.add_itxt_chunk(PROCESSING_KEY.to_string(), processing.clone())?;
+    }
+    if let Some(quality) = &metadata.quality {
+        encoder.add_itxt_chunk(QUALITY_KEY.to_string(), serde_json::to_string(quality)?)?;
+    }
+    if let Some(disk) = &metadata.disk {
+        encoder.add_itxt_chunk(DISK_GEOMETRY_KEY.to_string(), serde_json::to_string(disk)?)?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(buffer)
+}
+
+/// PNGのテキストチャンクから画像の情報を読み取る
+///
+/// PNGでない場合や撮影時刻が書かれていない場合は`None`になる。
+pub fn read(data: &[u8]) -> Option<Metadata> {
+    read_from(Cursor::new(data))
+}
+
+/// ファイルの先頭から画像の情報を読み取る。画素は読まない
+pub fn read_file(path: &Path) -> Option<Metadata> {
+    read_from(BufReader::new(File::open(path).ok()?))
+}
+
+fn read_from(reader: impl Read) -> Option<Metadata> {
+    let decoder = png::Decoder::new(reader);
+    let reader = decoder.read_info().ok()?;
+    let info = reader.info();
+    let text = |key: &str| {
+        let latin1 = info
+            .uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.clone());
+        latin1.or_else(|| {
+            info.utf8_text
+                .iter()
+                .find(|chunk| chunk.keyword == key)
+                .and_then(|chunk| chunk.get_text().ok())
+        })
+    };
+
+    let captured_at = DateTime::parse_from_rfc3339(&text(CAPTURE_TIME_KEY)?).ok()?;
+    Some(Metadata {
+        id: DownloadId::new(captured_at.with_timezone(&Utc)),
+        product: text(PRODUCT_KEY).filter(|product| product != FULL_DISK_PRODUCT),
+        level: text(ZOOM_LEVEL_KEY).and_then(|level| level.parse().ok()),
+        processing: text(PROCESSING_KEY),
+        quality: text(QUALITY_KEY).and_then(|quality| serde_json::from_str(&quality).ok()),
+        disk: text(DISK_GEOMETRY_KEY).and_then(|disk| serde_json::from_str(&disk).ok()),
+    })
+}
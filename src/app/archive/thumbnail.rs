@@ -0,0 +1,59 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use image::{codecs::jpeg::JpegEncoder, DynamicImage};
+
+use crate::himawari::DownloadId;
+
+use super::{write_atomic, DownloadedImage, IMAGE_DIR};
+
+/// サムネイルを保存するディレクトリ
+pub const DIR_NAME: &str = "thumbnails";
+/// サムネイルの一辺 [px]
+pub const SIZE: u32 = 180;
+const JPEG_QUALITY: u8 = 80;
+
+/// サムネイルの保存先。保存形式や並べ方に関係なく撮影時刻ごとに1つ
+pub fn path(id: DownloadId) -> PathBuf {
+    let timestamp = id.as_utc_datetime().format("%Y%m%d%H%M%S");
+    Path::new(IMAGE_DIR)
+        .join(DIR_NAME)
+        .join(format!("{timestamp}.jpg"))
+}
+
+/// 全球画像からサムネイルを作って保存する
+pub async fn save(id: DownloadId, image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let data = encode(image)?;
+    let path = path(id);
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    write_atomic(&path, &data).await?;
+    Ok(data)
+}
+
+/// サムネイルを読み込む。まだなければ保存済みの画像から作る
+pub async fn load(image: DownloadedImage) -> anyhow::Result<Vec<u8>> {
+    if let Ok(data) = tokio::fs::read(path(image.id)).await {
+        return Ok(data);
+    }
+
+    log::debug!("Create thumbnail: {:?}", image.id);
+    let data = image.read().await?;
+    // 縮小は重いのでUIのスレッドを止めないようにする
+    let thumbnail = tokio::task::spawn_blocking(move || {
+        anyhow::Ok(image::load_from_memory(&data)?.thumbnail(SIZE, SIZE))
+    })
+    .await??;
+    save(image.id, &thumbnail).await
+}
+
+fn encode(image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let image = image.thumbnail(SIZE, SIZE).to_rgb8();
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut Cursor::new(&mut data), JPEG_QUALITY)
+        .encode_image(&image)?;
+    Ok(data)
+}
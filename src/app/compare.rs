@@ -0,0 +1,278 @@
+use iced::advanced::layout::{self, Layout};
+use iced::advanced::renderer;
+use iced::advanced::widget::{self, tree, Widget};
+use iced::advanced::{self, Clipboard, Shell};
+use iced::event;
+use iced::mouse;
+use iced::touch;
+use iced::widget::image::Handle;
+use iced::{Color, Element, Event, Length, Point, Rectangle, Size};
+
+use super::downloaded_image::DownloadedImage;
+
+/// 境界線の太さ
+const DIVIDER_WIDTH: f32 = 3.0;
+/// 境界線の中央のつまみの大きさ
+const KNOB_SIZE: f32 = 36.0;
+
+/// 2枚の画像の比べ方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompareMode {
+    /// 左右に並べる
+    #[default]
+    SideBySide,
+    /// 重ねて、境界線より左に左の画像を、右に右の画像を表示する
+    Wipe,
+}
+
+/// 比べている2枚の画像
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// 撮影時刻の早い方
+    pub before: DownloadedImage,
+    pub after: DownloadedImage,
+    pub mode: CompareMode,
+    /// 展開した`before`と`after`の画像。展開し終わるまでは`None`
+    pub handles: Option<(Handle, Handle)>,
+    /// 最後に頼んだ展開の番号。古い展開の結果は捨てる
+    pub request: u64,
+}
+
+impl Comparison {
+    pub fn new(a: DownloadedImage, b: DownloadedImage) -> Self {
+        let (before, after) = if a.id <= b.id { (a, b) } else { (b, a) };
+        Self {
+            before,
+            after,
+            mode: CompareMode::default(),
+            handles: None,
+            request: 0,
+        }
+    }
+}
+
+/// 2枚の画像を並べたり重ねたりして比べるウィジェット
+///
+/// `Wipe`では画面をなぞると境界線が指に合わせて動く。
+pub struct Compare {
+    left: Handle,
+    right: Handle,
+    mode: CompareMode,
+}
+
+impl Compare {
+    pub fn new(left: Handle, right: Handle, mode: CompareMode) -> Self {
+        Self { left, right, mode }
+    }
+}
+
+struct State {
+    /// 境界線の位置。左端が0、右端が1
+    split: f32,
+    is_dragging: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            split: 0.5,
+            is_dragging: false,
+        }
+    }
+}
+
+/// 縦横比を保って`bounds`に収まる中央の矩形
+fn contain(image: Size<u32>, bounds: Rectangle) -> Rectangle {
+    let scale = (bounds.width / image.width as f32).min(bounds.height / image.height as f32);
+    let (width, height) = (image.width as f32 * scale, image.height as f32 * scale);
+    Rectangle {
+        x: bounds.center_x() - width / 2.0,
+        y: bounds.center_y() - height / 2.0,
+        width,
+        height,
+    }
+}
+
+impl<Message, Renderer> Widget<Message, Renderer> for Compare
+where
+    Renderer: advanced::image::Renderer<Handle = Handle>,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
+    }
+
+    fn width(&self) -> Length {
+        Length::Fill
+    }
+
+    fn height(&self) -> Length {
+        Length::Fill
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(Length::Fill).height(Length::Fill).max())
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut widget::Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        _shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) -> event::Status {
+        if self.mode != CompareMode::Wipe {
+            return event::Status::Ignored;
+        }
+        let bounds = layout.bounds();
+        let state = tree.state.downcast_mut::<State>();
+
+        let position = match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let position = cursor.position_over(bounds);
+                state.is_dragging = position.is_some();
+                position
+            }
+            Event::Touch(touch::Event::FingerPressed { position, .. })
+                if bounds.contains(position) =>
+            {
+                state.is_dragging = true;
+                Some(position)
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position })
+            | Event::Touch(touch::Event::FingerMoved { position, .. })
+                if state.is_dragging =>
+            {
+                Some(position)
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+            | Event::Touch(touch::Event::FingerLifted { .. })
+            | Event::Touch(touch::Event::FingerLost { .. })
+                if state.is_dragging =>
+            {
+                state.is_dragging = false;
+                return event::Status::Captured;
+            }
+            _ => None,
+        };
+        let Some(position) = position else {
+            return event::Status::Ignored;
+        };
+        state.split = ((position.x - bounds.x) / bounds.width).clamp(0.0, 1.0);
+        event::Status::Captured
+    }
+
+    fn draw(
+        &self,
+        tree: &widget::Tree,
+        renderer: &mut Renderer,
+        _theme: &<Renderer as advanced::Renderer>::Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let left_size = renderer.dimensions(&self.left);
+        let right_size = renderer.dimensions(&self.right);
+
+        match self.mode {
+            CompareMode::SideBySide => {
+                let half = Rectangle {
+                    width: bounds.width / 2.0,
+                    ..bounds
+                };
+                renderer.draw(self.left.clone(), contain(left_size, half));
+                let half = Rectangle {
+                    x: bounds.x + bounds.width / 2.0,
+                    ..half
+                };
+                renderer.draw(self.right.clone(), contain(right_size, half));
+            }
+            CompareMode::Wipe => {
+                let split = tree.state.downcast_ref::<State>().split;
+                let split_x = bounds.x + bounds.width * split;
+                let left_bounds = Rectangle {
+                    width: split_x - bounds.x,
+                    ..bounds
+                };
+                let right_bounds = Rectangle {
+                    x: split_x,
+                    width: bounds.x + bounds.width - split_x,
+                    ..bounds
+                };
+                // 同じ位置に描いて、境界線の左右で切り取る
+                renderer.with_layer(left_bounds, |renderer| {
+                    renderer.draw(self.left.clone(), contain(left_size, bounds));
+                });
+                renderer.with_layer(right_bounds, |renderer| {
+                    renderer.draw(self.right.clone(), contain(right_size, bounds));
+                });
+
+                renderer.with_layer(bounds, |renderer| {
+                    renderer.fill_quad(
+                        renderer::Quad {
+                            bounds: Rectangle::new(
+                                Point::new(split_x - DIVIDER_WIDTH / 2.0, bounds.y),
+                                Size::new(DIVIDER_WIDTH, bounds.height),
+                            ),
+                            border_radius: Default::default(),
+                            border_width: 0.0,
+                            border_color: Color::TRANSPARENT,
+                        },
+                        Color::WHITE,
+                    );
+                    renderer.fill_quad(
+                        renderer::Quad {
+                            bounds: Rectangle::new(
+                                Point::new(
+                                    split_x - KNOB_SIZE / 2.0,
+                                    bounds.center_y() - KNOB_SIZE / 2.0,
+                                ),
+                                Size::new(KNOB_SIZE, KNOB_SIZE),
+                            ),
+                            border_radius: (KNOB_SIZE / 2.0).into(),
+                            border_width: DIVIDER_WIDTH,
+                            border_color: Color::WHITE,
+                        },
+                        Color {
+                            a: 0.6,
+                            ..Color::BLACK
+                        },
+                    );
+                });
+            }
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        _tree: &widget::Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        if self.mode == CompareMode::Wipe && cursor.is_over(layout.bounds()) {
+            mouse::Interaction::ResizingHorizontally
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
+
+impl<'a, Message, Renderer> From<Compare> for Element<'a, Message, Renderer>
+where
+    Renderer: 'a + advanced::image::Renderer<Handle = Handle>,
+{
+    fn from(compare: Compare) -> Self {
+        Element::new(compare)
+    }
+}
@@ -2,35 +2,131 @@ use std::path::PathBuf;
 
 use iced::{
     theme,
-    widget::{button, text},
-    Color, Element,
+    widget::{button, image as iced_image, text, Column, Space},
+    Alignment, Color, Element,
 };
 
-use crate::himawari::DownloadId;
+use crate::{
+    framing::DiskGeometry,
+    himawari::DownloadId,
+    quality::{Assessment, Grade},
+};
 
-use super::Message;
+use super::{
+    archive::{
+        bundle::{self, Member},
+        thumbnail,
+    },
+    Message,
+};
 
 #[derive(Debug, Clone)]
 pub struct DownloadedImage {
-    pub path: PathBuf,
+    pub source: Source,
     pub id: DownloadId,
+    /// 派生した画像の種類。全球画像は`None`
+    pub product: Option<String>,
+    /// 画像の品質。調べていない場合は`None`
+    pub quality: Option<Assessment>,
+    /// 画像の中での全球画像の配置。記録されていない場合は`None`
+    pub disk: Option<DiskGeometry>,
+}
+
+/// 保存された画像の場所
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    /// 1日分の画像をまとめたファイルの中
+    Bundled {
+        path: PathBuf,
+        member: Member,
+    },
 }
 
 impl DownloadedImage {
-    pub fn view(&self, is_selected: bool) -> Element<Message> {
+    /// 表示用のハンドル
+    ///
+    /// まとめたファイルに入っている場合は読み込むのに時間がかかるので`None`を返す。
+    /// `frame_cache::decode`で裏で展開して使う。
+    pub fn handle(&self) -> Option<iced_image::Handle> {
+        match &self.source {
+            Source::File(path) => Some(iced_image::Handle::from_path(path)),
+            Source::Bundled { .. } => None,
+        }
+    }
+
+    /// 画像ファイルの中身を読み込む
+    pub async fn read(&self) -> anyhow::Result<Vec<u8>> {
+        match &self.source {
+            Source::File(path) => Ok(tokio::fs::read(path).await?),
+            Source::Bundled { path, member } => {
+                let (path, member) = (path.clone(), member.clone());
+                tokio::task::spawn_blocking(move || bundle::read(&path, &member)).await?
+            }
+        }
+    }
+
+    /// 表示や再生から外すべき画像か
+    pub fn is_bad(&self) -> bool {
+        self.quality
+            .as_ref()
+            .is_some_and(|quality| quality.grade == Grade::Bad)
+    }
+
+    pub fn view(
+        &self,
+        is_selected: bool,
+        thumbnail: Option<&iced_image::Handle>,
+    ) -> Element<'_, Message> {
         let timestamp = self.id.as_local_datetime().format("%Y-%m-%d %H:%M");
         let text_color = if is_selected {
             Color::from_rgb8(0xff, 0xf1, 0x00) // Yellow
+        } else if self.is_bad() {
+            Color::from_rgb8(0x80, 0x80, 0x80) // Gray
         } else {
             Color::WHITE
         };
-        button(
-            text(timestamp)
-                .size(30)
-                .style(theme::Text::Color(text_color)),
+        // 読み込むまでは同じ大きさの空白を置いて、一覧がずれないようにする
+        let thumbnail: Element<'_, Message> = match thumbnail {
+            Some(handle) => iced_image::Image::new(handle.clone())
+                .width(thumbnail::SIZE as f32)
+                .height(thumbnail::SIZE as f32)
+                .into(),
+            None => Space::new(thumbnail::SIZE as f32, thumbnail::SIZE as f32).into(),
+        };
+        let reasons = self
+            .quality
+            .as_ref()
+            .filter(|quality| quality.grade != Grade::Good)
+            .map(|quality| {
+                let reason_color = match quality.grade {
+                    Grade::Bad => Color::from_rgb8(0xe6, 0x00, 0x12), // Red
+                    _ => Color::from_rgb8(0xa0, 0xa0, 0xa0),          // Light gray
+                };
+                text(quality.reasons.join(", "))
+                    .size(14)
+                    .style(theme::Text::Color(reason_color))
+                    .into()
+            });
+        let label = Column::with_children(
+            [
+                thumbnail,
+                text(timestamp)
+                    .size(18)
+                    .style(theme::Text::Color(text_color))
+                    .into(),
+            ]
+            .into_iter()
+            .chain(reasons)
+            .collect(),
         )
-        .on_press(Message::SelectImage(self.clone()))
-        .style(theme::Button::Text)
-        .into()
+        .align_items(Alignment::Center)
+        .spacing(4);
+        button(label)
+            .on_press(Message::SelectImage(self.clone()))
+            .width(thumbnail::SIZE as f32)
+            .padding(0)
+            .style(theme::Button::Text)
+            .into()
     }
 }
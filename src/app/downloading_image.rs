@@ -1,35 +1,44 @@
-use std::sync::Arc;
-
 use iced::{
     theme,
     widget::{container, text},
     Color, Element, Subscription,
 };
 
-use crate::himawari::{download_subscription, DownloadId};
+use crate::himawari::{download_subscription, DownloadId, Resize};
 
-use super::Message;
+use super::{zoom::Detail, Message};
 
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct DownloadingImage {
     pub id: DownloadId,
     pub state: DownloadState,
+    /// ダウンロードしたタイルを縮小する大きさ
+    resize: Resize,
+    /// ダウンロードし終わったタイル。表示中の画像に重ねて途中経過を見せる
+    pub preview: Option<Detail>,
 }
 
 impl DownloadingImage {
-    pub fn new(id: DownloadId) -> Self {
+    pub fn new(id: DownloadId, resize: Resize) -> Self {
         DownloadingImage {
             id,
             state: DownloadState::Starting,
+            resize,
+            preview: None,
         }
     }
 
+    pub fn is_failed(&self) -> bool {
+        matches!(self.state, DownloadState::Failed)
+    }
+
     pub fn subscription(&self) -> Subscription<Message> {
-        download_subscription(self.id).map(|(id, p)| Message::DownloadProgressed(id, p))
+        download_subscription(self.id, self.resize)
+            .map(|(id, p)| Message::DownloadProgressed(id, p))
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
         let timestamp = self.id.as_local_datetime().format("%Y-%m-%d %H:%M");
         container(
             text(timestamp)
@@ -44,7 +53,7 @@ impl DownloadingImage {
 #[derive(Debug)]
 pub enum DownloadState {
     Starting,
-    Downloading { progress: f32 },
+    Downloading,
     Finished,
-    Failed(Arc<anyhow::Error>),
+    Failed,
 }
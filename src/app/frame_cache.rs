@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+
+use iced::advanced::image::Data;
+use iced::widget::image::Handle;
+
+use crate::himawari::DownloadId;
+
+use super::downloaded_image::DownloadedImage;
+
+/// 撮影時刻と`product`
+type Key = (DownloadId, Option<String>);
+
+fn key(image: &DownloadedImage) -> Key {
+    (image.id, image.product.clone())
+}
+
+/// 展開済みの画像のキャッシュ
+///
+/// `Handle::from_path`のままだと、切り替えるたびに描画の途中でPNGを展開して止まってしまう。
+/// 表示しそうな画像をあらかじめ裏で展開しておき、RGBAの画素のまま持っておく。
+/// 合計の大きさが上限を超えたら、最も長く使っていないものから捨てる。
+pub struct FrameCache {
+    /// 持っておく画素の合計の上限 [byte]
+    capacity: usize,
+    /// 古く使ったものから順に並べる
+    entries: VecDeque<(Key, Handle, usize)>,
+    size: usize,
+    /// 展開を待っている画像
+    queue: VecDeque<DownloadedImage>,
+    /// 展開中の画像
+    loading: Option<Key>,
+}
+
+impl FrameCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+            size: 0,
+            queue: VecDeque::new(),
+            loading: None,
+        }
+    }
+
+    pub fn get(&self, image: &DownloadedImage) -> Option<&Handle> {
+        let key = key(image);
+        self.entries
+            .iter()
+            .find(|(k, ..)| *k == key)
+            .map(|(_, handle, _)| handle)
+    }
+
+    /// `images`を前から順に展開する。前に頼んだ先読みは取りやめる
+    ///
+    /// すでに展開済みの画像は最近使ったことにして、捨てられにくくする。
+    /// 次に展開し始める画像があれば返す。
+    pub fn prefetch(&mut self, images: Vec<DownloadedImage>) -> Option<DownloadedImage> {
+        for image in images.iter().rev() {
+            let key = key(image);
+            if let Some(i) = self.entries.iter().position(|(k, ..)| *k == key) {
+                let entry = self.entries.remove(i).unwrap();
+                self.entries.push_back(entry);
+            }
+        }
+        self.queue = images
+            .into_iter()
+            .filter(|image| self.get(image).is_none())
+            .collect();
+        self.next()
+    }
+
+    /// 展開し終わった画像を加える。次に展開し始める画像があれば返す
+    pub fn loaded(
+        &mut self,
+        image: &DownloadedImage,
+        handle: Option<Handle>,
+    ) -> Option<DownloadedImage> {
+        self.loading = None;
+        if let Some(handle) = handle {
+            self.insert(key(image), handle);
+        }
+        self.next()
+    }
+
+    fn insert(&mut self, key: Key, handle: Handle) {
+        let size = match handle.data() {
+            Data::Rgba { pixels, .. } => pixels.len(),
+            _ => 0,
+        };
+        if size > self.capacity || self.entries.iter().any(|(k, ..)| *k == key) {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let Some((_, _, evicted)) = self.entries.pop_front() else {
+                break;
+            };
+            self.size -= evicted;
+        }
+        self.entries.push_back((key, handle, size));
+        self.size += size;
+    }
+
+    /// 展開中でなければ、待っている画像のうちまだ展開していないものを取り出す
+    fn next(&mut self) -> Option<DownloadedImage> {
+        if self.loading.is_some() {
+            return None;
+        }
+        while let Some(image) = self.queue.pop_front() {
+            if self.get(&image).is_none() {
+                self.loading = Some(key(&image));
+                return Some(image);
+            }
+        }
+        None
+    }
+}
+
+/// 画像を読み込んでRGBAに展開する。展開は重いのでUIのスレッドを止めないようにする
+pub async fn decode(image: DownloadedImage) -> anyhow::Result<Handle> {
+    let data = image.read().await?;
+    tokio::task::spawn_blocking(move || {
+        let decoded = image::load_from_memory(&data)?.to_rgba8();
+        let (width, height) = decoded.dimensions();
+        anyhow::Ok(Handle::from_pixels(width, height, decoded.into_raw()))
+    })
+    .await?
+}
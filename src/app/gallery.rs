@@ -0,0 +1,381 @@
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+};
+
+use chrono::{Datelike, Months, NaiveDate};
+use iced::{
+    theme,
+    widget::{button, container, row, scrollable, text, Column, Row, Space},
+    Alignment, Color, Command, Element, Length,
+};
+
+use crate::himawari::DownloadId;
+
+use super::{archive::thumbnail, downloaded_image::DownloadedImage, Message};
+
+/// 1行に並べるサムネイルの数
+const COLUMNS: usize = 3;
+/// 一覧の高さ
+const HEIGHT: f32 = 600.0;
+/// 日付の見出しの高さ
+const HEADER_HEIGHT: f32 = 50.0;
+/// サムネイル1行の高さ。品質の悪い理由の表示も収まるようにする
+const ROW_HEIGHT: f32 = thumbnail::SIZE as f32 + 70.0;
+/// 見えている範囲の前後にも並べておく高さ。速くスクロールしても空白が見えにくいように
+const OVERSCAN: f32 = ROW_HEIGHT * 2.0;
+
+/// 保存済みの画像の一覧
+///
+/// 何週間分もの画像をすべて並べるとPiでは重いので、行の高さを固定して見えている行だけを並べる。
+pub struct Gallery {
+    /// 折りたたんだ日
+    collapsed: HashSet<NaiveDate>,
+    /// スクロールした量
+    offset: f32,
+    /// カレンダーで表示している月の初日。一覧を表示しているときは`None`
+    calendar: Option<NaiveDate>,
+    scrollable_id: scrollable::Id,
+}
+
+/// 撮影日(ローカル時刻)ごとにまとめた画像
+struct Section<'a> {
+    date: NaiveDate,
+    /// 撮影時刻順
+    images: &'a [DownloadedImage],
+}
+
+enum Item<'a> {
+    /// 日付と画像の数
+    Header(NaiveDate, usize),
+    /// 新しい順に並べた1行分の画像
+    Row(Vec<&'a DownloadedImage>),
+}
+
+impl Item<'_> {
+    fn height(&self) -> f32 {
+        match self {
+            Item::Header(..) => HEADER_HEIGHT,
+            Item::Row(_) => ROW_HEIGHT,
+        }
+    }
+}
+
+impl Gallery {
+    pub fn new() -> Self {
+        Self {
+            collapsed: HashSet::new(),
+            offset: 0.0,
+            calendar: None,
+            scrollable_id: scrollable::Id::unique(),
+        }
+    }
+
+    /// メニューを開き直したときは一番上(最新の画像)から表示する
+    pub fn reset(&mut self) {
+        self.offset = 0.0;
+        self.calendar = None;
+    }
+
+    pub fn scrolled(&mut self, offset: f32) {
+        self.offset = offset;
+    }
+
+    pub fn toggle_day(&mut self, date: NaiveDate) {
+        if !self.collapsed.remove(&date) {
+            self.collapsed.insert(date);
+        }
+    }
+
+    /// `date`を含む月のカレンダーを表示する
+    pub fn show_calendar(&mut self, date: NaiveDate) {
+        self.calendar = date.with_day(1);
+    }
+
+    /// カレンダーを閉じて一覧に戻る
+    pub fn hide_calendar(&mut self) -> Command<Message> {
+        self.calendar = None;
+        // 一覧を作り直すとスクロール位置が失われるので戻す
+        self.scroll_to(self.offset)
+    }
+
+    /// `date`の画像までスクロールする
+    pub fn jump_to(&mut self, date: NaiveDate, images: &[DownloadedImage]) -> Command<Message> {
+        self.calendar = None;
+        self.collapsed.remove(&date);
+        let sections = sections(images);
+        let offset = self
+            .items(&sections)
+            .into_iter()
+            .take_while(|item| !matches!(item, Item::Header(header, _) if *header == date))
+            .map(|item| item.height())
+            .sum();
+        self.scroll_to(offset)
+    }
+
+    fn scroll_to(&mut self, offset: f32) -> Command<Message> {
+        self.offset = offset;
+        scrollable::scroll_to(
+            self.scrollable_id.clone(),
+            scrollable::AbsoluteOffset { x: 0.0, y: offset },
+        )
+    }
+
+    /// 見えている(もうすぐ見える)画像。サムネイルを先に読み込む
+    pub fn visible_images(&self, images: &[DownloadedImage]) -> Vec<DownloadedImage> {
+        if self.calendar.is_some() {
+            return vec![];
+        }
+        let sections = sections(images);
+        let mut top = 0.0;
+        let mut visible = vec![];
+        for item in self.items(&sections) {
+            let height = item.height();
+            if let Item::Row(images) = item {
+                if self.is_visible(top, height) {
+                    visible.extend(images.into_iter().cloned());
+                }
+            }
+            top += height;
+        }
+        visible
+    }
+
+    fn is_visible(&self, top: f32, height: f32) -> bool {
+        top + height > self.offset - OVERSCAN && top < self.offset + HEIGHT + OVERSCAN
+    }
+
+    /// 一覧に並ぶ見出しと行を上から順に
+    fn items<'a>(&self, sections: &[Section<'a>]) -> Vec<Item<'a>> {
+        let mut items = vec![];
+        for section in sections {
+            items.push(Item::Header(section.date, section.images.len()));
+            if !self.collapsed.contains(&section.date) {
+                items.extend(
+                    section
+                        .images
+                        .rchunks(COLUMNS)
+                        .map(|images| Item::Row(images.iter().rev().collect())),
+                );
+            }
+        }
+        items
+    }
+
+    pub fn view<'a>(
+        &'a self,
+        images: &'a [DownloadedImage],
+        thumbnails: &'a HashMap<DownloadId, Option<iced::widget::image::Handle>>,
+        current_id: Option<DownloadId>,
+    ) -> Element<'a, Message> {
+        let sections = sections(images);
+        if let Some(month) = self.calendar {
+            return calendar(month, &sections, current_id);
+        }
+
+        // 見えていない行はまとめて空白にする
+        let mut children: Vec<Element<'a, Message>> = vec![];
+        let (mut top, mut space) = (0.0, 0.0);
+        for item in self.items(&sections) {
+            let height = item.height();
+            top += height;
+            if !self.is_visible(top - height, height) {
+                space += height;
+                continue;
+            }
+            if space > 0.0 {
+                children.push(Space::with_height(space).into());
+                space = 0.0;
+            }
+            children.push(match item {
+                Item::Header(date, count) => header(date, count, self.collapsed.contains(&date)),
+                Item::Row(images) => container(
+                    Row::with_children(
+                        images
+                            .into_iter()
+                            .map(|image| {
+                                let thumbnail = thumbnails.get(&image.id).and_then(Option::as_ref);
+                                image.view(current_id == Some(image.id), thumbnail)
+                            })
+                            .collect(),
+                    )
+                    .spacing(10),
+                )
+                .height(ROW_HEIGHT)
+                .into(),
+            });
+        }
+        if space > 0.0 {
+            children.push(Space::with_height(space).into());
+        }
+
+        scrollable(Column::with_children(children).width(Length::Fill))
+            .id(self.scrollable_id.clone())
+            .on_scroll(|viewport| Message::GalleryScrolled(viewport.absolute_offset().y))
+            .width(Length::Fill)
+            .direction(scrollable::Direction::Vertical(
+                scrollable::Properties::new().width(50).scroller_width(50),
+            ))
+            .height(HEIGHT)
+            .into()
+    }
+
+    /// カレンダーと一覧を切り替えるボタン
+    pub fn toggle_button(&self, images: &[DownloadedImage]) -> Element<'_, Message> {
+        let message = match self.calendar {
+            Some(_) => Message::HideCalendar,
+            None => {
+                let date = images
+                    .last()
+                    .map(|image| image.id.as_local_datetime().date_naive())
+                    .unwrap_or_else(|| chrono::Local::now().date_naive());
+                Message::ShowCalendar(date)
+            }
+        };
+        let label = if self.calendar.is_some() {
+            "List"
+        } else {
+            "Calendar"
+        };
+        button(text(label).size(30))
+            .on_press(message)
+            .style(theme::Button::Text)
+            .into()
+    }
+}
+
+/// 新しい日から順に
+fn sections(images: &[DownloadedImage]) -> Vec<Section<'_>> {
+    images
+        .chunk_by(|a, b| local_date(a) == local_date(b))
+        .rev()
+        .map(|images| Section {
+            date: local_date(&images[0]),
+            images,
+        })
+        .collect()
+}
+
+fn local_date(image: &DownloadedImage) -> NaiveDate {
+    image.id.as_local_datetime().date_naive()
+}
+
+fn header<'a>(date: NaiveDate, count: usize, is_collapsed: bool) -> Element<'a, Message> {
+    let marker = if is_collapsed { "+" } else { "-" };
+    button(
+        row![
+            text(marker).size(30).width(30),
+            text(date.format("%Y-%m-%d (%a)")).size(30),
+            text(format!("{count} images"))
+                .size(20)
+                .style(theme::Text::Color(Color::from_rgb8(0x80, 0x80, 0x80))),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(20),
+    )
+    .on_press(Message::ToggleDay(date))
+    .height(HEADER_HEIGHT)
+    .width(Length::Fill)
+    .style(theme::Button::Text)
+    .into()
+}
+
+/// 月のカレンダー。画像のある日を押すとその日まで移動する
+fn calendar<'a>(
+    month: NaiveDate,
+    sections: &[Section<'_>],
+    current_id: Option<DownloadId>,
+) -> Element<'a, Message> {
+    const CELL_SIZE: f32 = 80.0;
+    let dates = sections
+        .iter()
+        .map(|section| section.date)
+        .collect::<HashSet<_>>();
+    let current_date = current_id.map(|id| id.as_local_datetime().date_naive());
+
+    let previous = month - Months::new(1);
+    let next = month + Months::new(1);
+    let navigation = row![
+        button(text("<").size(30))
+            .on_press(Message::ShowCalendar(previous))
+            .style(theme::Button::Text),
+        text(month.format("%Y-%m"))
+            .size(30)
+            .width(Length::Fill)
+            .horizontal_alignment(iced::alignment::Horizontal::Center),
+        button(text(">").size(30))
+            .on_press(Message::ShowCalendar(next))
+            .style(theme::Button::Text),
+    ]
+    .align_items(Alignment::Center);
+
+    let weekdays = Row::with_children(
+        ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"]
+            .into_iter()
+            .map(|weekday| {
+                text(weekday)
+                    .size(20)
+                    .width(CELL_SIZE)
+                    .horizontal_alignment(iced::alignment::Horizontal::Center)
+                    .into()
+            })
+            .collect(),
+    );
+
+    // 日曜始まりで、月初めの前は空白で埋める
+    let blanks = month.weekday().num_days_from_sunday() as usize;
+    let days = month
+        .iter_days()
+        .take_while(|date| date.month() == month.month())
+        .map(Some);
+    let cells = iter::repeat_n(None, blanks).chain(days).collect::<Vec<_>>();
+    let weeks = cells.chunks(7).map(|week| {
+        Row::with_children(
+            week.iter()
+                .map(|date| match date {
+                    Some(date) => {
+                        let color = if Some(*date) == current_date {
+                            Color::from_rgb8(0xff, 0xf1, 0x00) // Yellow
+                        } else if dates.contains(date) {
+                            Color::WHITE
+                        } else {
+                            Color::from_rgb8(0x50, 0x50, 0x50)
+                        };
+                        let label = text(date.day())
+                            .size(30)
+                            .width(Length::Fill)
+                            .horizontal_alignment(iced::alignment::Horizontal::Center)
+                            .style(theme::Text::Color(color));
+                        let cell = button(label)
+                            .width(CELL_SIZE)
+                            .height(CELL_SIZE)
+                            .style(theme::Button::Text);
+                        // 画像のない日は押せない
+                        if dates.contains(date) {
+                            cell.on_press(Message::JumpToDay(*date)).into()
+                        } else {
+                            cell.into()
+                        }
+                    }
+                    None => Space::new(CELL_SIZE, CELL_SIZE).into(),
+                })
+                .collect(),
+        )
+        .into()
+    });
+
+    container(
+        Column::with_children(
+            [navigation.into(), weekdays.into()]
+                .into_iter()
+                .chain(weeks)
+                .collect(),
+        )
+        .width(CELL_SIZE * 7.0)
+        .spacing(5),
+    )
+    .width(Length::Fill)
+    .height(HEIGHT)
+    .center_x()
+    .into()
+}
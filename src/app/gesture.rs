@@ -0,0 +1,343 @@
+use std::time::{Duration, Instant};
+
+use iced::advanced::layout::{self, Layout};
+use iced::advanced::overlay;
+use iced::advanced::renderer;
+use iced::advanced::widget::{self, tree, Widget};
+use iced::advanced::{self, Clipboard, Shell};
+use iced::event;
+use iced::mouse;
+use iced::touch;
+use iced::window;
+use iced::{Element, Event, Length, Point, Rectangle};
+
+/// 長押しとみなすまでの時間
+const LONG_PRESS: Duration = Duration::from_millis(600);
+/// 続けてタップしたときにダブルタップとみなす間隔
+const DOUBLE_TAP: Duration = Duration::from_millis(300);
+/// スワイプとみなす横方向の移動量
+const SWIPE_DISTANCE: f32 = 80.0;
+/// これ以上動いたら長押しやタップとみなさない
+const SLOP: f32 = 20.0;
+
+/// 画面上の指の動き
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    SwipeLeft,
+    SwipeRight,
+    Tap,
+    DoubleTap,
+    LongPress,
+}
+
+impl Gesture {
+    /// 設定の`keymap`で使う名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Gesture::SwipeLeft => "SwipeLeft",
+            Gesture::SwipeRight => "SwipeRight",
+            Gesture::Tap => "Tap",
+            Gesture::DoubleTap => "DoubleTap",
+            Gesture::LongPress => "LongPress",
+        }
+    }
+}
+
+/// 指やマウスで画面をなぞったり長押ししたりしたことを伝えるウィジェット
+///
+/// 1本目の指だけを追いかける。マウスの左ボタンでのドラッグも同じように扱う。
+/// イベントは下のウィジェットにも渡すので、拡大した画像のドラッグなどと組み合わせられる。
+pub struct Gestures<'a, Message, Renderer> {
+    base: Element<'a, Message, Renderer>,
+    on_gesture: Box<dyn Fn(Gesture) -> Message + 'a>,
+    swipes: bool,
+}
+
+impl<'a, Message, Renderer> Gestures<'a, Message, Renderer> {
+    pub fn new(
+        base: impl Into<Element<'a, Message, Renderer>>,
+        on_gesture: impl Fn(Gesture) -> Message + 'a,
+    ) -> Self {
+        Self {
+            base: base.into(),
+            on_gesture: Box::new(on_gesture),
+            swipes: true,
+        }
+    }
+
+    /// スワイプを判定するか。ドラッグを下のウィジェットで使う場合は`false`にする
+    pub fn swipes(self, swipes: bool) -> Self {
+        Self { swipes, ..self }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    press: Option<Press>,
+    /// 直前のタップの位置と時刻
+    last_tap: Option<(Point, Instant)>,
+}
+
+impl State {
+    /// 指を離したときに、押し始めからの動きをジェスチャーとして判定する
+    fn release(&mut self, position: Point, swipes: bool) -> Option<Gesture> {
+        let gesture = self.press.take()?.gesture(position, swipes)?;
+        if gesture != Gesture::Tap {
+            return Some(gesture);
+        }
+        let now = Instant::now();
+        match self.last_tap.take() {
+            Some((last, at)) if now - at <= DOUBLE_TAP && last.distance(position) <= SLOP * 2.0 => {
+                Some(Gesture::DoubleTap)
+            }
+            _ => {
+                self.last_tap = Some((position, now));
+                Some(Gesture::Tap)
+            }
+        }
+    }
+}
+
+struct Press {
+    /// 指で押した場合はその指。マウスなら`None`
+    finger: Option<touch::Finger>,
+    origin: Point,
+    started_at: Instant,
+    /// `SLOP`より大きく動いたか
+    has_moved: bool,
+    /// 長押しをすでに伝えたか
+    is_long_pressed: bool,
+    /// 2本目の指で押されたか。ピンチ操作なのでジェスチャーとみなさない
+    is_cancelled: bool,
+}
+
+impl Press {
+    fn is_same_finger(&self, finger: touch::Finger) -> bool {
+        self.finger == Some(finger)
+    }
+
+    /// 指を離した位置から、タップかスワイプかを判定する
+    fn gesture(&self, position: Point, swipes: bool) -> Option<Gesture> {
+        if self.is_long_pressed || self.is_cancelled {
+            return None;
+        }
+        let dx = position.x - self.origin.x;
+        let dy = position.y - self.origin.y;
+        if swipes && dx.abs() >= SWIPE_DISTANCE && dx.abs() > dy.abs() {
+            Some(if dx < 0.0 {
+                Gesture::SwipeLeft
+            } else {
+                Gesture::SwipeRight
+            })
+        } else if !self.has_moved {
+            Some(Gesture::Tap)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for Gestures<'a, Message, Renderer>
+where
+    Renderer: advanced::Renderer,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
+    }
+
+    fn children(&self) -> Vec<widget::Tree> {
+        vec![widget::Tree::new(&self.base)]
+    }
+
+    fn diff(&self, tree: &mut widget::Tree) {
+        tree.diff_children(&[&self.base]);
+    }
+
+    fn width(&self) -> Length {
+        self.base.as_widget().width()
+    }
+
+    fn height(&self) -> Length {
+        self.base.as_widget().height()
+    }
+
+    fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        self.base.as_widget().layout(renderer, limits)
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut widget::Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        renderer: &Renderer,
+        clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        viewport: &Rectangle,
+    ) -> event::Status {
+        let status = self.base.as_widget_mut().on_event(
+            &mut tree.children[0],
+            event.clone(),
+            layout,
+            cursor,
+            renderer,
+            clipboard,
+            shell,
+            viewport,
+        );
+        let state = tree.state.downcast_mut::<State>();
+
+        // 押し始め(`finger`が`None`ならマウス)
+        let pressed = match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => cursor
+                .position_over(layout.bounds())
+                .map(|position| (None, position)),
+            Event::Touch(touch::Event::FingerPressed { id, position })
+                if layout.bounds().contains(position) =>
+            {
+                Some((Some(id), position))
+            }
+            _ => None,
+        };
+        if let Some((finger, origin)) = pressed {
+            match &mut state.press {
+                Some(press) => press.is_cancelled = true,
+                None => {
+                    let started_at = Instant::now();
+                    state.press = Some(Press {
+                        finger,
+                        origin,
+                        started_at,
+                        has_moved: false,
+                        is_long_pressed: false,
+                        is_cancelled: false,
+                    });
+                    // 指が動かなくても長押しに気づけるように再描画してもらう
+                    shell.request_redraw(window::RedrawRequest::At(started_at + LONG_PRESS));
+                }
+            }
+            return event::Status::Captured;
+        }
+
+        let Some(press) = state.press.as_mut() else {
+            return status;
+        };
+
+        match event {
+            Event::Mouse(mouse::Event::CursorMoved { position }) if press.finger.is_none() => {
+                press.has_moved |= press.origin.distance(position) > SLOP;
+            }
+            Event::Touch(touch::Event::FingerMoved { id, position })
+                if press.is_same_finger(id) =>
+            {
+                press.has_moved |= press.origin.distance(position) > SLOP;
+            }
+            Event::Window(window::Event::RedrawRequested(now))
+                if !press.has_moved && !press.is_long_pressed && !press.is_cancelled =>
+            {
+                if now >= press.started_at + LONG_PRESS {
+                    press.is_long_pressed = true;
+                    shell.publish((self.on_gesture)(Gesture::LongPress));
+                } else {
+                    shell.request_redraw(window::RedrawRequest::At(press.started_at + LONG_PRESS));
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+                if press.finger.is_none() =>
+            {
+                let position = cursor.position().unwrap_or(press.origin);
+                if let Some(gesture) = state.release(position, self.swipes) {
+                    shell.publish((self.on_gesture)(gesture));
+                }
+            }
+            Event::Touch(touch::Event::FingerLifted { id, position })
+                if press.is_same_finger(id) =>
+            {
+                if let Some(gesture) = state.release(position, self.swipes) {
+                    shell.publish((self.on_gesture)(gesture));
+                }
+            }
+            Event::Touch(touch::Event::FingerLost { id, .. }) if press.is_same_finger(id) => {
+                state.press = None;
+            }
+            _ => return status,
+        }
+        event::Status::Captured
+    }
+
+    fn draw(
+        &self,
+        tree: &widget::Tree,
+        renderer: &mut Renderer,
+        theme: &<Renderer as advanced::Renderer>::Theme,
+        style: &renderer::Style,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        viewport: &Rectangle,
+    ) {
+        self.base.as_widget().draw(
+            &tree.children[0],
+            renderer,
+            theme,
+            style,
+            layout,
+            cursor,
+            viewport,
+        );
+    }
+
+    fn overlay<'b>(
+        &'b mut self,
+        tree: &'b mut widget::Tree,
+        layout: Layout<'_>,
+        renderer: &Renderer,
+    ) -> Option<overlay::Element<'b, Message, Renderer>> {
+        self.base
+            .as_widget_mut()
+            .overlay(&mut tree.children[0], layout, renderer)
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &widget::Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        viewport: &Rectangle,
+        renderer: &Renderer,
+    ) -> mouse::Interaction {
+        self.base.as_widget().mouse_interaction(
+            &tree.children[0],
+            layout,
+            cursor,
+            viewport,
+            renderer,
+        )
+    }
+
+    fn operate(
+        &self,
+        tree: &mut widget::Tree,
+        layout: Layout<'_>,
+        renderer: &Renderer,
+        operation: &mut dyn widget::Operation<Message>,
+    ) {
+        self.base
+            .as_widget()
+            .operate(&mut tree.children[0], layout, renderer, operation);
+    }
+}
+
+impl<'a, Message, Renderer> From<Gestures<'a, Message, Renderer>> for Element<'a, Message, Renderer>
+where
+    Renderer: 'a + advanced::Renderer,
+    Message: 'a,
+{
+    fn from(gestures: Gestures<'a, Message, Renderer>) -> Self {
+        Element::new(gestures)
+    }
+}
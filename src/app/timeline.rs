@@ -0,0 +1,361 @@
+use chrono::{DateTime, Duration, DurationRound, Local, Timelike, Utc};
+use iced::advanced::layout::{self, Layout};
+use iced::advanced::overlay;
+use iced::advanced::renderer;
+use iced::advanced::widget::{self, tree, Widget};
+use iced::advanced::{self, Clipboard, Shell};
+use iced::event;
+use iced::mouse;
+use iced::touch;
+use iced::{Color, Element, Event, Length, Point, Rectangle};
+
+use super::downloaded_image::DownloadedImage;
+
+/// 帯の高さ
+const HEIGHT: f32 = 60.0;
+/// ひまわりが全球画像を撮影する間隔
+const SLOT_MINUTES: i64 = 10;
+/// 撮影してから配信されるまでの時間。これより新しい枠は欠けていても隙間として描かない
+const PUBLISH_DELAY_MINUTES: i64 = 30;
+
+/// 画面の下端に直近24時間の帯を重ねて表示するウィジェット
+///
+/// 保存済みの画像の位置に目盛りを、画像のない枠に隙間を描く。帯を指でなぞると、指の位置に一番近い画像を次々に選ぶ。
+pub struct Timeline<'a, Message, Renderer> {
+    base: Element<'a, Message, Renderer>,
+    /// 撮影時刻順に並んだ画像
+    images: &'a [DownloadedImage],
+    /// 表示中の画像の撮影時刻
+    current: Option<DateTime<Utc>>,
+    /// 帯の右端の時刻
+    end: DateTime<Utc>,
+    on_scrub: Box<dyn Fn(&DownloadedImage) -> Message + 'a>,
+}
+
+impl<'a, Message, Renderer> Timeline<'a, Message, Renderer> {
+    pub fn new(
+        base: impl Into<Element<'a, Message, Renderer>>,
+        images: &'a [DownloadedImage],
+        current: Option<DateTime<Utc>>,
+        on_scrub: impl Fn(&DownloadedImage) -> Message + 'a,
+    ) -> Self {
+        Self {
+            base: base.into(),
+            images,
+            current,
+            end: Utc::now(),
+            on_scrub: Box::new(on_scrub),
+        }
+    }
+
+    fn start(&self) -> DateTime<Utc> {
+        self.end - Duration::hours(24)
+    }
+
+    /// 帯に入る画像
+    fn visible_images(&self) -> &'a [DownloadedImage] {
+        let start = self.start();
+        let from = self
+            .images
+            .partition_point(|image| image.id.as_utc_datetime() < start);
+        &self.images[from..]
+    }
+
+    fn x(&self, bar: Rectangle, datetime: DateTime<Utc>) -> f32 {
+        let elapsed = (datetime - self.start()).num_seconds() as f32;
+        bar.x + bar.width * elapsed / (24.0 * 60.0 * 60.0)
+    }
+
+    /// `x`に一番近い、表示できる画像
+    fn image_at(&self, bar: Rectangle, x: f32) -> Option<&'a DownloadedImage> {
+        self.visible_images()
+            .iter()
+            .filter(|image| !image.is_bad())
+            .min_by(|a, b| {
+                let a = (self.x(bar, a.id.as_utc_datetime()) - x).abs();
+                let b = (self.x(bar, b.id.as_utc_datetime()) - x).abs();
+                a.total_cmp(&b)
+            })
+    }
+
+    fn scrub(&self, state: &mut State, bar: Rectangle, x: f32, shell: &mut Shell<'_, Message>) {
+        let Some(image) = self.image_at(bar, x) else {
+            return;
+        };
+        // 同じ画像を何度も選び直さない
+        if state.last != Some(image.id.as_utc_datetime()) {
+            state.last = Some(image.id.as_utc_datetime());
+            shell.publish((self.on_scrub)(image));
+        }
+    }
+
+    fn draw_bar(&self, renderer: &mut Renderer, bar: Rectangle)
+    where
+        Renderer: advanced::Renderer,
+    {
+        let mut fill = |bounds: Rectangle, color: Color| {
+            renderer.fill_quad(
+                renderer::Quad {
+                    bounds,
+                    border_radius: Default::default(),
+                    border_width: 0.0,
+                    border_color: Color::TRANSPARENT,
+                },
+                color,
+            );
+        };
+        fill(
+            bar,
+            Color {
+                a: 0.6,
+                ..Color::BLACK
+            },
+        );
+
+        // 1時間ごとの目盛り。6時間ごとに長くする
+        let mut hour = self
+            .start()
+            .duration_trunc(Duration::hours(1))
+            .unwrap_or(self.start())
+            + Duration::hours(1);
+        while hour < self.end {
+            let is_major = DateTime::<Local>::from(hour).hour() % 6 == 0;
+            let height = if is_major { 16.0 } else { 8.0 };
+            fill(
+                Rectangle::new(Point::new(self.x(bar, hour), bar.y), [1.0, height].into()),
+                Color::from_rgba8(0xff, 0xff, 0xff, 0.4),
+            );
+            hour += Duration::hours(1);
+        }
+
+        // 画像のない枠を下端に赤く描く
+        let images = self.visible_images();
+        let slot = Duration::minutes(SLOT_MINUTES);
+        let mut slot_start = self.start().duration_trunc(slot).unwrap_or(self.start());
+        while slot_start + slot < self.end - Duration::minutes(PUBLISH_DELAY_MINUTES) {
+            let slot_end = slot_start + slot;
+            let is_missing = !images.iter().any(|image| {
+                let datetime = image.id.as_utc_datetime();
+                slot_start <= datetime && datetime < slot_end
+            });
+            if is_missing && slot_start >= self.start() {
+                let (x0, x1) = (self.x(bar, slot_start), self.x(bar, slot_end));
+                fill(
+                    Rectangle::new(
+                        Point::new(x0, bar.y + bar.height - 6.0),
+                        [x1 - x0, 6.0].into(),
+                    ),
+                    Color::from_rgba8(0xe6, 0x00, 0x12, 0.8),
+                );
+            }
+            slot_start = slot_end;
+        }
+
+        // 保存済みの画像の目盛り。表示しない画像は暗くする
+        for image in images {
+            let color = if image.is_bad() {
+                Color::from_rgba8(0x80, 0x80, 0x80, 0.6)
+            } else {
+                Color::WHITE
+            };
+            let x = self.x(bar, image.id.as_utc_datetime());
+            fill(
+                Rectangle::new(
+                    Point::new(x - 1.0, bar.y + bar.height / 2.0 - 12.0),
+                    [2.0, 24.0].into(),
+                ),
+                color,
+            );
+        }
+
+        if let Some(current) = self.current.filter(|current| *current >= self.start()) {
+            let x = self.x(bar, current);
+            fill(
+                Rectangle::new(Point::new(x - 2.0, bar.y), [4.0, bar.height].into()),
+                Color::from_rgb8(0xff, 0xf1, 0x00), // Yellow
+            );
+        }
+    }
+}
+
+/// 帯の部分の矩形
+fn bar_bounds(bounds: Rectangle) -> Rectangle {
+    Rectangle {
+        y: bounds.y + bounds.height - HEIGHT,
+        height: HEIGHT,
+        ..bounds
+    }
+}
+
+#[derive(Default)]
+struct State {
+    is_scrubbing: bool,
+    /// 最後に選んだ画像の撮影時刻
+    last: Option<DateTime<Utc>>,
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for Timeline<'a, Message, Renderer>
+where
+    Renderer: advanced::Renderer,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
+    }
+
+    fn children(&self) -> Vec<widget::Tree> {
+        vec![widget::Tree::new(&self.base)]
+    }
+
+    fn diff(&self, tree: &mut widget::Tree) {
+        tree.diff_children(&[&self.base]);
+    }
+
+    fn width(&self) -> Length {
+        self.base.as_widget().width()
+    }
+
+    fn height(&self) -> Length {
+        self.base.as_widget().height()
+    }
+
+    fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        self.base.as_widget().layout(renderer, limits)
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut widget::Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        renderer: &Renderer,
+        clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        viewport: &Rectangle,
+    ) -> event::Status {
+        let bar = bar_bounds(layout.bounds());
+        let state = tree.state.downcast_mut::<State>();
+
+        // 指やマウスの位置。帯の外で押した場合は下のウィジェットに任せる
+        let (position, is_pressed) = match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                (cursor.position(), true)
+            }
+            Event::Touch(touch::Event::FingerPressed { position, .. }) => (Some(position), true),
+            Event::Mouse(mouse::Event::CursorMoved { position })
+            | Event::Touch(touch::Event::FingerMoved { position, .. }) => (Some(position), false),
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+            | Event::Touch(touch::Event::FingerLifted { .. })
+            | Event::Touch(touch::Event::FingerLost { .. })
+                if state.is_scrubbing =>
+            {
+                state.is_scrubbing = false;
+                state.last = None;
+                return event::Status::Captured;
+            }
+            _ => (None, false),
+        };
+        if let Some(position) = position {
+            if is_pressed && bar.contains(position) {
+                state.is_scrubbing = true;
+            }
+            if state.is_scrubbing {
+                self.scrub(state, bar, position.x, shell);
+                return event::Status::Captured;
+            }
+        }
+
+        self.base.as_widget_mut().on_event(
+            &mut tree.children[0],
+            event,
+            layout,
+            cursor,
+            renderer,
+            clipboard,
+            shell,
+            viewport,
+        )
+    }
+
+    fn draw(
+        &self,
+        tree: &widget::Tree,
+        renderer: &mut Renderer,
+        theme: &<Renderer as advanced::Renderer>::Theme,
+        style: &renderer::Style,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        viewport: &Rectangle,
+    ) {
+        self.base.as_widget().draw(
+            &tree.children[0],
+            renderer,
+            theme,
+            style,
+            layout,
+            cursor,
+            viewport,
+        );
+
+        // 画像より上に描くために別のレイヤーにする
+        let bar = bar_bounds(layout.bounds());
+        renderer.with_layer(bar, |renderer| self.draw_bar(renderer, bar));
+    }
+
+    fn overlay<'b>(
+        &'b mut self,
+        tree: &'b mut widget::Tree,
+        layout: Layout<'_>,
+        renderer: &Renderer,
+    ) -> Option<overlay::Element<'b, Message, Renderer>> {
+        self.base
+            .as_widget_mut()
+            .overlay(&mut tree.children[0], layout, renderer)
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &widget::Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        viewport: &Rectangle,
+        renderer: &Renderer,
+    ) -> mouse::Interaction {
+        if cursor.is_over(bar_bounds(layout.bounds())) {
+            return mouse::Interaction::Pointer;
+        }
+        self.base.as_widget().mouse_interaction(
+            &tree.children[0],
+            layout,
+            cursor,
+            viewport,
+            renderer,
+        )
+    }
+
+    fn operate(
+        &self,
+        tree: &mut widget::Tree,
+        layout: Layout<'_>,
+        renderer: &Renderer,
+        operation: &mut dyn widget::Operation<Message>,
+    ) {
+        self.base
+            .as_widget()
+            .operate(&mut tree.children[0], layout, renderer, operation);
+    }
+}
+
+impl<'a, Message, Renderer> From<Timeline<'a, Message, Renderer>> for Element<'a, Message, Renderer>
+where
+    Renderer: 'a + advanced::Renderer,
+    Message: 'a,
+{
+    fn from(timeline: Timeline<'a, Message, Renderer>) -> Self {
+        Element::new(timeline)
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use iced::advanced::image::Data;
+use iced::widget::image::Handle;
+use image::{imageops, DynamicImage, GrayImage, RgbaImage};
+use rayon::prelude::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
+
+/// 動きを推定するときに縮小する割合
+const MOTION_SCALE: u32 = 4;
+/// 縮小した画像上で動きを推定するブロックの大きさ [px]
+const BLOCK_SIZE: u32 = 8;
+/// 縮小した画像上で探す動きの範囲 [px]
+const SEARCH_RADIUS: i32 = 4;
+
+/// 画像を切り替えるときのクロスフェード
+///
+/// 途中の画像はあらかじめCPUで合成しておき、表示するときは順番に差し替えるだけにする。
+/// GPUのないPiでも描画が重くならない。
+/// 合成が`duration`のうちに終わらなければ、クロスフェードせずに切り替える。
+pub struct Transition {
+    /// 合成を頼んだときの番号。古い合成の結果は捨てる
+    pub request: u64,
+    /// 切り替える前に表示していた画像
+    from: Handle,
+    /// 合成した途中の画像。合成し終わるまでは`None`
+    frames: Option<Vec<Handle>>,
+    duration: Duration,
+    /// 合成し終わって表示し始めた時刻
+    started: Option<Instant>,
+    now: Option<Instant>,
+    /// 立てると合成を途中でやめる。クロスフェードを捨てたときに立てる
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Transition {
+    pub fn new(request: u64, from: Handle, duration: Duration) -> Self {
+        Self {
+            request,
+            from,
+            frames: None,
+            duration,
+            started: None,
+            now: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 途中の画像を合成する。`duration`のうちに終わらないか、このクロスフェードを捨てたら`None`を返す
+    pub fn blend(
+        &self,
+        to: Handle,
+        count: u32,
+        interpolate: bool,
+    ) -> impl std::future::Future<Output = anyhow::Result<Option<Vec<Handle>>>> {
+        blend(
+            self.from.clone(),
+            to,
+            count,
+            interpolate,
+            Instant::now() + self.duration,
+            self.cancelled.clone(),
+        )
+    }
+
+    /// まだ合成しているか
+    pub fn is_blending(&self) -> bool {
+        self.frames.is_none()
+    }
+
+    /// 合成し終わった途中の画像を表示し始める
+    pub fn start(&mut self, frames: Vec<Handle>) {
+        self.frames = Some(frames);
+        self.started = Some(Instant::now());
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// 時刻を進める。終わったら`false`を返す
+    pub fn tick(&mut self, now: Instant) -> bool {
+        self.now = Some(now);
+        self.started
+            .is_none_or(|started| now.duration_since(started) < self.duration)
+    }
+
+    /// いま表示する画像
+    pub fn frame(&self) -> &Handle {
+        let (Some(frames), Some(started)) = (&self.frames, self.started) else {
+            return &self.from;
+        };
+        let elapsed = self
+            .now
+            .map_or(Duration::ZERO, |now| now.duration_since(started));
+        let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        let i = (progress * frames.len() as f32) as usize;
+        frames.get(i).or(frames.last()).unwrap_or(&self.from)
+    }
+}
+
+impl Drop for Transition {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// `from`から`to`に切り替わる途中の`count`枚の画像を合成する
+///
+/// `interpolate`なら雲の動きを大まかに推定し、動かしながら重ねる。
+/// 大きさの違う画像どうしは合成できない。
+/// `deadline`を過ぎるか`cancelled`が立ったら、残りは合成せずに`None`を返す。
+async fn blend(
+    from: Handle,
+    to: Handle,
+    count: u32,
+    interpolate: bool,
+    deadline: Instant,
+    cancelled: Arc<AtomicBool>,
+) -> anyhow::Result<Option<Vec<Handle>>> {
+    tokio::task::spawn_blocking(move || {
+        let gives_up = || cancelled.load(Ordering::Relaxed) || Instant::now() >= deadline;
+        if gives_up() {
+            return Ok(None);
+        }
+        let from = decode(&from)?;
+        let to = decode(&to)?;
+        if from.dimensions() != to.dimensions() {
+            anyhow::bail!(
+                "cannot blend images of different sizes: {:?} and {:?}",
+                from.dimensions(),
+                to.dimensions()
+            );
+        }
+        let motion = interpolate.then(|| Motion::estimate(&from, &to));
+        let mut frames = vec![];
+        for i in 1..=count {
+            if gives_up() {
+                return Ok(None);
+            }
+            let t = i as f32 / (count + 1) as f32;
+            let frame = match &motion {
+                Some(motion) => motion.interpolate(&from, &to, t),
+                None => mix(&from, &to, t),
+            };
+            let (width, height) = frame.dimensions();
+            frames.push(Handle::from_pixels(width, height, frame.into_raw()));
+        }
+        Ok((!gives_up()).then_some(frames))
+    })
+    .await?
+}
+
+/// ハンドルの画像を読み込む
+fn decode(handle: &Handle) -> anyhow::Result<RgbaImage> {
+    let image = match handle.data() {
+        Data::Path(path) => image::open(path)?.to_rgba8(),
+        Data::Bytes(bytes) => image::load_from_memory(bytes)?.to_rgba8(),
+        Data::Rgba {
+            width,
+            height,
+            pixels,
+        } => RgbaImage::from_raw(*width, *height, pixels.to_vec())
+            .ok_or_else(|| anyhow::anyhow!("invalid pixels"))?,
+    };
+    Ok(image)
+}
+
+/// 単純に重ねる
+fn mix(from: &RgbaImage, to: &RgbaImage, t: f32) -> RgbaImage {
+    let mut mixed = from.clone();
+    let weight = (t * 256.0) as u32;
+    mixed
+        .par_chunks_mut(4096)
+        .zip(to.par_chunks(4096))
+        .for_each(|(mixed, to)| {
+            for (a, b) in mixed.iter_mut().zip(to) {
+                *a = ((*a as u32 * (256 - weight) + *b as u32 * weight) >> 8) as u8;
+            }
+        });
+    mixed
+}
+
+/// ブロックごとの大まかな動き
+struct Motion {
+    /// 横に並ぶブロックの数
+    columns: u32,
+    rows: u32,
+    /// ブロックごとの`from`から`to`への動き [元の画像のpx]
+    vectors: Vec<(f32, f32)>,
+}
+
+impl Motion {
+    /// 縮小した明るさの画像でブロックマッチングする
+    fn estimate(from: &RgbaImage, to: &RgbaImage) -> Self {
+        let (width, height) = from.dimensions();
+        let small = |image: &RgbaImage| -> GrayImage {
+            DynamicImage::ImageRgba8(image.clone())
+                .resize_exact(
+                    (width / MOTION_SCALE).max(1),
+                    (height / MOTION_SCALE).max(1),
+                    imageops::FilterType::Triangle,
+                )
+                .to_luma8()
+        };
+        let (from, to) = (small(from), small(to));
+        let (small_width, small_height) = from.dimensions();
+        let columns = small_width.div_ceil(BLOCK_SIZE);
+        let rows = small_height.div_ceil(BLOCK_SIZE);
+
+        let vectors = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let (left, top) = (column * BLOCK_SIZE, row * BLOCK_SIZE);
+                // ずれの小さいものを優先するので、動かないときの差から始める
+                let mut best = ((0, 0), block_difference(&from, &to, left, top, 0, 0));
+                for dy in -SEARCH_RADIUS..=SEARCH_RADIUS {
+                    for dx in -SEARCH_RADIUS..=SEARCH_RADIUS {
+                        let difference = block_difference(&from, &to, left, top, dx, dy);
+                        if difference < best.1 {
+                            best = ((dx, dy), difference);
+                        }
+                    }
+                }
+                let ((dx, dy), _) = best;
+                (
+                    (dx * MOTION_SCALE as i32) as f32,
+                    (dy * MOTION_SCALE as i32) as f32,
+                )
+            })
+            .collect();
+        Self {
+            columns,
+            rows,
+            vectors,
+        }
+    }
+
+    /// 元の画像上の位置の動き
+    fn at(&self, x: u32, y: u32) -> (f32, f32) {
+        let size = BLOCK_SIZE * MOTION_SCALE;
+        let column = (x / size).min(self.columns - 1);
+        let row = (y / size).min(self.rows - 1);
+        self.vectors[(row * self.columns + column) as usize]
+    }
+
+    /// 両側の画像を途中まで動かしてから重ねる
+    fn interpolate(&self, from: &RgbaImage, to: &RgbaImage, t: f32) -> RgbaImage {
+        let (width, height) = from.dimensions();
+        let weight = (t * 256.0) as u32;
+        let sample = |image: &RgbaImage, x: f32, y: f32| {
+            let x = (x.round() as i64).clamp(0, width as i64 - 1) as u32;
+            let y = (y.round() as i64).clamp(0, height as i64 - 1) as u32;
+            image.get_pixel(x, y).0
+        };
+        let mut blended = RgbaImage::new(width, height);
+        blended
+            .par_chunks_mut(width as usize * 4)
+            .enumerate()
+            .for_each(|(y, line)| {
+                let y = y as u32;
+                for (x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                    let x = x as u32;
+                    let (dx, dy) = self.at(x, y);
+                    let a = sample(from, x as f32 - dx * t, y as f32 - dy * t);
+                    let b = sample(to, x as f32 + dx * (1.0 - t), y as f32 + dy * (1.0 - t));
+                    for ((p, a), b) in pixel.iter_mut().zip(a).zip(b) {
+                        *p = ((a as u32 * (256 - weight) + b as u32 * weight) >> 8) as u8;
+                    }
+                }
+            });
+        blended
+    }
+}
+
+/// `from`のブロックと、`(dx, dy)`ずらした`to`のブロックの差の絶対値の和。はみ出す部分は比べない
+fn block_difference(
+    from: &GrayImage,
+    to: &GrayImage,
+    left: u32,
+    top: u32,
+    dx: i32,
+    dy: i32,
+) -> u32 {
+    let (width, height) = from.dimensions();
+    let mut sum = 0;
+    let mut count = 0;
+    for y in top..(top + BLOCK_SIZE).min(height) {
+        for x in left..(left + BLOCK_SIZE).min(width) {
+            let (tx, ty) = (x as i32 + dx, y as i32 + dy);
+            if tx < 0 || ty < 0 || tx >= width as i32 || ty >= height as i32 {
+                continue;
+            }
+            let a = from.get_pixel(x, y).0[0];
+            let b = to.get_pixel(tx as u32, ty as u32).0[0];
+            sum += a.abs_diff(b) as u32;
+            count += 1;
+        }
+    }
+    // はみ出して比べた画素が少ないほど有利にならないように平均する
+    (sum * 256).checked_div(count).unwrap_or(u32::MAX)
+}
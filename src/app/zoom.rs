@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+
+use iced::advanced::layout::{self, Layout};
+use iced::advanced::renderer;
+use iced::advanced::widget::{self, tree, Widget};
+use iced::advanced::{self, Clipboard, Shell};
+use iced::event;
+use iced::mouse;
+use iced::touch;
+use iced::widget::image::Handle;
+use iced::{Element, Event, Length, Point, Rectangle, Size, Vector};
+use image::RgbImage;
+
+use crate::{
+    framing::DiskGeometry,
+    himawari::{self, DownloadId, FullDisk, TILE_SIZE},
+};
+
+/// 拡大率の上限
+const MAX_SCALE: f32 = 10.0;
+/// マウスホイール1行あたりの拡大率
+const WHEEL_STEP: f32 = 1.2;
+/// タッチパッドなどのスクロール量で`WHEEL_STEP`倍になる量 [px]
+const WHEEL_PIXELS_PER_STEP: f32 = 50.0;
+
+/// 画像の拡大率と表示している位置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    /// 1で画像全体が画面に収まる
+    pub scale: f32,
+    /// 画面の中央に表示する画像上の位置。画像の幅と高さを1とする
+    pub center: Point,
+    /// 拡大率1のときの画面の大きさ。画像の幅と高さを1とする
+    ///
+    /// 画像と画面の縦横比が違うと、どちらかが1より大きくなる。
+    pub screen: Size,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            center: Point::new(0.5, 0.5),
+            screen: Size::new(1.0, 1.0),
+        }
+    }
+}
+
+impl Viewport {
+    pub fn is_zoomed(&self) -> bool {
+        self.scale > 1.0
+    }
+
+    /// 画面に見えている画像上の範囲。画像の幅と高さを1とする
+    ///
+    /// 画像の外側は含まない。
+    pub fn visible(&self) -> Rectangle {
+        let (half_width, half_height) = self.half_screen();
+        let left = (self.center.x - half_width).max(0.0);
+        let top = (self.center.y - half_height).max(0.0);
+        let right = (self.center.x + half_width).min(1.0);
+        let bottom = (self.center.y + half_height).min(1.0);
+        Rectangle {
+            x: left,
+            y: top,
+            width: (right - left).max(0.0),
+            height: (bottom - top).max(0.0),
+        }
+    }
+
+    /// 画面に見えている画像上の範囲の幅と高さの半分。画像より大きくはならない
+    fn half_screen(&self) -> (f32, f32) {
+        (
+            (0.5 * self.screen.width / self.scale).min(0.5),
+            (0.5 * self.screen.height / self.scale).min(0.5),
+        )
+    }
+
+    /// 画像上の位置`focus`が画面上で動かないように拡大・縮小する
+    fn zoom(self, factor: f32, focus: Point) -> Self {
+        let scale = (self.scale * factor).clamp(1.0, MAX_SCALE);
+        let ratio = self.scale / scale;
+        let center = Point::new(
+            focus.x + (self.center.x - focus.x) * ratio,
+            focus.y + (self.center.y - focus.y) * ratio,
+        );
+        Self {
+            scale,
+            center,
+            ..self
+        }
+        .clamped()
+    }
+
+    /// 画像上で`delta`だけ表示位置を動かす
+    fn pan(self, delta: Vector) -> Self {
+        Self {
+            center: self.center - delta,
+            ..self
+        }
+        .clamped()
+    }
+
+    /// 画像の外側が見えないようにする。画像が画面より小さい向きは中央に置く
+    fn clamped(self) -> Self {
+        let (half_width, half_height) = self.half_screen();
+        Self {
+            center: Point::new(
+                self.center.x.clamp(half_width, 1.0 - half_width),
+                self.center.y.clamp(half_height, 1.0 - half_height),
+            ),
+            ..self
+        }
+    }
+}
+
+/// 保存した画像に重ねて描くタイル。拡大表示のための高解像度のタイルや、ダウンロード中の画像のタイル
+#[derive(Debug, Clone)]
+pub struct Detail {
+    pub id: DownloadId,
+    pub level: u32,
+    pub tiles: Vec<DetailTile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DetailTile {
+    pub x: u32,
+    pub y: u32,
+    pub handle: Handle,
+}
+
+impl DetailTile {
+    /// 地球の外側を透明にしたタイルを作る
+    ///
+    /// 保存した画像の背景が見えるように、宇宙の部分は描かない。
+    /// `tile`は縮小してあってもよい
+    pub fn new(level: u32, x: u32, y: u32, tile: &RgbImage) -> Self {
+        let tile_size = tile.width();
+        let rgba = FullDisk::new(tile_size * level).mask_space(tile, tile_size * x, tile_size * y);
+        Self {
+            x,
+            y,
+            handle: Handle::from_pixels(rgba.width(), rgba.height(), rgba.into_raw()),
+        }
+    }
+}
+
+/// `viewport`で見えている範囲を画面の解像度で表示するのに必要なズームレベルとタイルの位置
+///
+/// `geometry`は保存した画像の中での全球画像の配置。保存した画像より細かく表示する必要がなければ`None`を返す。
+pub fn required_tiles(
+    viewport: &Viewport,
+    geometry: &DiskGeometry,
+) -> Option<(u32, Vec<(u32, u32)>)> {
+    if !viewport.is_zoomed() {
+        return None;
+    }
+    let disk = geometry.size as f32;
+    let needed = disk * viewport.scale;
+    let level = himawari::LEVELS
+        .into_iter()
+        .find(|level| (TILE_SIZE * level) as f32 >= needed)
+        .unwrap_or(himawari::LEVELS[himawari::LEVELS.len() - 1]);
+    if level <= himawari::DOWNLOAD_LEVEL {
+        return None;
+    }
+
+    // 見えている範囲を全球画像の幅を1とする座標に直す
+    let visible = viewport.visible();
+    let to_disk = |value: f32, length: u32, offset: f64| {
+        ((value * length as f32 - offset as f32) / disk).clamp(0.0, 1.0)
+    };
+    let tile_range = |from: f32, to: f32, length: u32, offset: f64| {
+        let from = (to_disk(from, length, offset) * level as f32).floor() as u32;
+        let to = (to_disk(to, length, offset) * level as f32).ceil() as u32;
+        from..to.min(level)
+    };
+    let (width, height) = (geometry.width, geometry.height);
+    let xs = tile_range(visible.x, visible.x + visible.width, width, geometry.left);
+    let ys = tile_range(visible.y, visible.y + visible.height, height, geometry.top);
+    let positions = xs
+        .flat_map(|x| ys.clone().map(move |y| (x, y)))
+        .collect::<Vec<_>>();
+    (!positions.is_empty()).then_some((level, positions))
+}
+
+/// 指2本で拡大・縮小し、ドラッグで拡大した画像を動かせる画像
+///
+/// マウスではホイールで拡大・縮小する。拡大率と位置は`on_zoom`で伝え、次に描くときの`viewport`として受け取る。
+pub struct ZoomImage<'a, Message> {
+    handle: Handle,
+    viewport: Viewport,
+    geometry: DiskGeometry,
+    detail: Option<&'a Detail>,
+    on_zoom: Box<dyn Fn(Viewport) -> Message + 'a>,
+}
+
+impl<'a, Message> ZoomImage<'a, Message> {
+    pub fn new(
+        handle: Handle,
+        viewport: Viewport,
+        geometry: DiskGeometry,
+        on_zoom: impl Fn(Viewport) -> Message + 'a,
+    ) -> Self {
+        Self {
+            handle,
+            viewport,
+            geometry,
+            detail: None,
+            on_zoom: Box::new(on_zoom),
+        }
+    }
+
+    /// 画像の上に重ねて描くタイル
+    pub fn detail(self, detail: Option<&'a Detail>) -> Self {
+        Self { detail, ..self }
+    }
+
+    /// `viewport`に合わせて拡大した画像全体の矩形
+    fn image_bounds(viewport: &Viewport, bounds: Rectangle, image: Size<u32>) -> Rectangle {
+        let (image_width, image_height) = (image.width as f32, image.height as f32);
+        let fit = (bounds.width / image_width).min(bounds.height / image_height);
+        let width = image_width * fit * viewport.scale;
+        let height = image_height * fit * viewport.scale;
+        Rectangle {
+            x: bounds.center_x() - viewport.center.x * width,
+            y: bounds.center_y() - viewport.center.y * height,
+            width,
+            height,
+        }
+    }
+
+    /// 拡大率1のときの画面の大きさ。画像の幅と高さを1とする
+    fn screen(bounds: Rectangle, image: Size<u32>) -> Size {
+        let image_bounds = Self::image_bounds(&Viewport::default(), bounds, image);
+        Size::new(
+            bounds.width / image_bounds.width,
+            bounds.height / image_bounds.height,
+        )
+    }
+
+    /// 画面上の位置を画像の幅と高さを1とする座標に直す
+    fn to_image(image_bounds: Rectangle, position: Point) -> Point {
+        Point::new(
+            (position.x - image_bounds.x) / image_bounds.width,
+            (position.y - image_bounds.y) / image_bounds.height,
+        )
+    }
+
+    fn publish(&self, state: &mut State, viewport: Viewport, shell: &mut Shell<'_, Message>) {
+        if viewport != self.current(state) {
+            state.pending = Some(viewport);
+            shell.publish((self.on_zoom)(viewport));
+        }
+    }
+
+    /// 伝えた変更がまだ`viewport`に反映されていなくても、続けて操作できるようにする
+    fn current(&self, state: &State) -> Viewport {
+        state.pending.unwrap_or(self.viewport)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// 画面に触れている指の位置
+    fingers: HashMap<touch::Finger, Point>,
+    /// マウスでドラッグしているときの直前の位置
+    drag: Option<Point>,
+    /// `on_zoom`で伝えた最後の値。作り直されるまでは`viewport`より新しい
+    pending: Option<Viewport>,
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for ZoomImage<'a, Message>
+where
+    Renderer: advanced::image::Renderer<Handle = Handle>,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
+    }
+
+    fn diff(&self, tree: &mut widget::Tree) {
+        tree.state.downcast_mut::<State>().pending = None;
+    }
+
+    fn width(&self) -> Length {
+        Length::Fill
+    }
+
+    fn height(&self) -> Length {
+        Length::Fill
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(Length::Fill).height(Length::Fill).max())
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut widget::Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) -> event::Status {
+        let state = tree.state.downcast_mut::<State>();
+        let bounds = layout.bounds();
+        let image = renderer.dimensions(&self.handle);
+        let viewport = Viewport {
+            screen: Self::screen(bounds, image),
+            ..self.current(state)
+        };
+        let image_bounds = Self::image_bounds(&viewport, bounds, image);
+
+        match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let Some(position) = cursor.position_over(bounds) else {
+                    return event::Status::Ignored;
+                };
+                let steps = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / WHEEL_PIXELS_PER_STEP,
+                };
+                let focus = Self::to_image(image_bounds, position);
+                self.publish(state, viewport.zoom(WHEEL_STEP.powf(steps), focus), shell);
+                return event::Status::Captured;
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                state.drag = cursor.position_over(bounds);
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let Some(last) = state.drag.replace(position) else {
+                    return event::Status::Ignored;
+                };
+                if viewport.is_zoomed() {
+                    let delta = position - last;
+                    let delta =
+                        Vector::new(delta.x / image_bounds.width, delta.y / image_bounds.height);
+                    self.publish(state, viewport.pan(delta), shell);
+                    return event::Status::Captured;
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                state.drag = None;
+            }
+            Event::Touch(touch::Event::FingerPressed { id, position })
+                if bounds.contains(position) =>
+            {
+                state.fingers.insert(id, position);
+            }
+            Event::Touch(touch::Event::FingerMoved { id, position }) => {
+                let Some(last) = state.fingers.insert(id, position) else {
+                    return event::Status::Ignored;
+                };
+                let other = state
+                    .fingers
+                    .iter()
+                    .find(|(finger, _)| **finger != id)
+                    .map(|(_, position)| *position);
+                let (viewport, is_pinching) = match other {
+                    // 2本の指の間の距離の変化で拡大・縮小し、中点の移動で動かす
+                    Some(other) => {
+                        let factor = position.distance(other) / last.distance(other).max(1.0);
+                        let last_middle =
+                            Point::new((last.x + other.x) / 2.0, (last.y + other.y) / 2.0);
+                        let middle =
+                            Point::new((position.x + other.x) / 2.0, (position.y + other.y) / 2.0);
+                        let zoomed =
+                            viewport.zoom(factor, Self::to_image(image_bounds, last_middle));
+                        let image_bounds = Self::image_bounds(&zoomed, bounds, image);
+                        let delta = middle - last_middle;
+                        let delta = Vector::new(
+                            delta.x / image_bounds.width,
+                            delta.y / image_bounds.height,
+                        );
+                        (zoomed.pan(delta), true)
+                    }
+                    None if viewport.is_zoomed() => {
+                        let delta = position - last;
+                        let delta = Vector::new(
+                            delta.x / image_bounds.width,
+                            delta.y / image_bounds.height,
+                        );
+                        (viewport.pan(delta), false)
+                    }
+                    None => return event::Status::Ignored,
+                };
+                self.publish(state, viewport, shell);
+                if is_pinching || viewport.is_zoomed() {
+                    return event::Status::Captured;
+                }
+            }
+            Event::Touch(
+                touch::Event::FingerLifted { id, .. } | touch::Event::FingerLost { id, .. },
+            ) => {
+                state.fingers.remove(&id);
+            }
+            _ => {}
+        }
+        event::Status::Ignored
+    }
+
+    fn draw(
+        &self,
+        _tree: &widget::Tree,
+        renderer: &mut Renderer,
+        _theme: &<Renderer as advanced::Renderer>::Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let image = renderer.dimensions(&self.handle);
+        let image_bounds = Self::image_bounds(&self.viewport, bounds, image);
+
+        renderer.with_layer(bounds, |renderer| {
+            renderer.draw(self.handle.clone(), image_bounds);
+
+            let Some(detail) = self.detail else {
+                return;
+            };
+            // 保存した画像の中に置かれた全球画像の矩形
+            let geometry = &self.geometry;
+            let scale_x = image_bounds.width / geometry.width as f32;
+            let scale_y = image_bounds.height / geometry.height as f32;
+            let disk_width = geometry.size as f32 * scale_x;
+            let disk_height = geometry.size as f32 * scale_y;
+            let disk_x = image_bounds.x + geometry.left as f32 * scale_x;
+            let disk_y = image_bounds.y + geometry.top as f32 * scale_y;
+            let tile_width = disk_width / detail.level as f32;
+            let tile_height = disk_height / detail.level as f32;
+            for tile in &detail.tiles {
+                let tile_bounds = Rectangle {
+                    x: disk_x + tile_width * tile.x as f32,
+                    y: disk_y + tile_height * tile.y as f32,
+                    width: tile_width,
+                    height: tile_height,
+                };
+                if tile_bounds.intersects(&bounds) {
+                    renderer.draw(tile.handle.clone(), tile_bounds);
+                }
+            }
+        });
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &widget::Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        let state = tree.state.downcast_ref::<State>();
+        if !self.viewport.is_zoomed() || !cursor.is_over(layout.bounds()) {
+            return mouse::Interaction::default();
+        }
+        if state.drag.is_some() {
+            mouse::Interaction::Grabbing
+        } else {
+            mouse::Interaction::Grab
+        }
+    }
+}
+
+impl<'a, Message, Renderer> From<ZoomImage<'a, Message>> for Element<'a, Message, Renderer>
+where
+    Renderer: 'a + advanced::image::Renderer<Handle = Handle>,
+    Message: 'a,
+{
+    fn from(image: ZoomImage<'a, Message>) -> Self {
+        Element::new(image)
+    }
+}
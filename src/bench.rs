@@ -0,0 +1,222 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use futures::future::try_join_all;
+use image::{imageops, RgbImage};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::himawari::{self, Resize, TilePipeline, DOWNLOAD_LEVEL};
+
+/// 確保中のメモリの量を数えるアロケータ
+///
+/// `bench`でメモリの使い方を測るために使う。確保のたびに数える分だけ遅くなるので、
+/// `bench`フィーチャーをつけてビルドしたときだけ使う。
+pub struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+impl CountingAllocator {
+    fn add(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+    }
+
+    fn sub(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::sub(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::add(new_size);
+            Self::sub(layout.size());
+        }
+        new_ptr
+    }
+}
+
+/// 最大値を今の値に戻して、今の値を返す
+fn reset_peak() -> usize {
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(allocated, Ordering::Relaxed);
+    allocated
+}
+
+/// `base`からの最大の増加量 [MB]
+fn peak_since(base: usize) -> f64 {
+    PEAK.load(Ordering::Relaxed).saturating_sub(base) as f64 / 1024.0 / 1024.0
+}
+
+/// ダウンロードしたタイル。`arrival`はダウンロードし始めてから届くまでの時間
+struct Tile {
+    x: u32,
+    y: u32,
+    data: Vec<u8>,
+    arrival: Duration,
+}
+
+/// 1回の計測の結果
+struct Measurement {
+    /// 最後のタイルが届いてから全球画像ができるまでの時間
+    latency: Duration,
+    /// 処理中に増えたメモリの最大量 [MB]
+    peak_mb: f64,
+}
+
+/// 最新の画像のタイルを1回だけダウンロードし、届いた時刻を再現しながら2つの方法で全球画像を作って比べる
+///
+/// - buffered: 4枚すべて届いてから順に展開し、並列に縮小してつなぎ合わせる(以前の方法)
+/// - streamed: 届いたタイルから`TilePipeline`で展開・縮小する
+pub fn pipeline(resize: Resize, runs: u32) -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut tiles = runtime.block_on(download())?;
+    tiles.sort_by_key(|tile| tile.arrival);
+    let arrivals = tiles
+        .iter()
+        .map(|tile| format!("{:.0}ms", tile.arrival.as_secs_f64() * 1000.0))
+        .collect::<Vec<_>>();
+    println!("tile arrivals: {}", arrivals.join(", "));
+    println!("tile size: {}px, filter: {:?}", resize.size, resize.filter);
+
+    // スレッドプールなどの初回だけの確保を計測に含めない
+    buffered(&tiles, resize)?;
+    runtime.block_on(streamed(&tiles, resize))?;
+
+    let mut results = vec![];
+    for run in 1..=runs {
+        let buffered = buffered(&tiles, resize)?;
+        let streamed = runtime.block_on(streamed(&tiles, resize))?;
+        println!(
+            "run {run}: buffered {:>6.1}ms {:>6.1}MB | streamed {:>6.1}ms {:>6.1}MB",
+            buffered.latency.as_secs_f64() * 1000.0,
+            buffered.peak_mb,
+            streamed.latency.as_secs_f64() * 1000.0,
+            streamed.peak_mb,
+        );
+        results.push((buffered, streamed));
+    }
+    if !results.is_empty() {
+        let n = results.len() as f64;
+        let mean =
+            |f: &dyn Fn(&(Measurement, Measurement)) -> f64| results.iter().map(f).sum::<f64>() / n;
+        println!(
+            "mean:  buffered {:>6.1}ms {:>6.1}MB | streamed {:>6.1}ms {:>6.1}MB",
+            mean(&|(b, _)| b.latency.as_secs_f64() * 1000.0),
+            mean(&|(b, _)| b.peak_mb),
+            mean(&|(_, s)| s.latency.as_secs_f64() * 1000.0),
+            mean(&|(_, s)| s.peak_mb),
+        );
+    }
+    Ok(())
+}
+
+async fn download() -> anyhow::Result<Vec<Tile>> {
+    let id = himawari::fetch_download_info().await?;
+    let client = reqwest::Client::new();
+    let started = Instant::now();
+    let downloads = [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(x, y)| {
+        let request = client.get(id.tile_url(DOWNLOAD_LEVEL, x, y)).send();
+        async move {
+            let data = request.await?.error_for_status()?.bytes().await?.to_vec();
+            anyhow::Ok(Tile {
+                x,
+                y,
+                data,
+                arrival: started.elapsed(),
+            })
+        }
+    });
+    try_join_all(downloads).await
+}
+
+/// 以前の方法。すべて届いてから始めるので、最後のタイルが届いた時点から測る
+fn buffered(tiles: &[Tile], resize: Resize) -> anyhow::Result<Measurement> {
+    let base = reset_peak();
+    let started = Instant::now();
+    // ダウンロードしたデータを4枚分持っている状態から始める
+    let data = tiles
+        .iter()
+        .map(|tile| (tile.x, tile.y, tile.data.clone()))
+        .collect::<Vec<_>>();
+    let images = data
+        .iter()
+        .map(|(_, _, data)| image::load_from_memory(data))
+        .collect::<Result<Vec<_>, _>>()?;
+    let images = images
+        .into_par_iter()
+        .map(|image| image.resize(resize.size, resize.size, resize.filter.into()))
+        .collect::<Vec<_>>();
+    let mut combined = RgbImage::new(resize.size * 2, resize.size * 2);
+    for ((x, y, _), image) in data.iter().zip(images) {
+        let (x, y) = ((resize.size * x) as i64, (resize.size * y) as i64);
+        imageops::replace(&mut combined, &image.to_rgb8(), x, y);
+    }
+    let latency = started.elapsed();
+    drop(combined);
+    Ok(Measurement {
+        latency,
+        peak_mb: peak_since(base),
+    })
+}
+
+/// 新しい方法。届いた時刻どおりにタイルを渡し、最後のタイルを渡してから全球画像ができるまでを測る
+///
+/// 途中で表示するためのタイルも作るので、その分も含めて測る。
+async fn streamed(tiles: &[Tile], resize: Resize) -> anyhow::Result<Measurement> {
+    let base = reset_peak();
+    let started = tokio::time::Instant::now();
+    let mut pipeline = TilePipeline::new(resize, 2, 2);
+    let mut last_arrival = Instant::now();
+    for tile in tiles {
+        // 待っている間も、縮小し終わったタイルは受け取って捨てる
+        let deadline = started + tile.arrival;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => break,
+                Some(resized) = pipeline.next() => {
+                    resized?;
+                }
+            }
+        }
+        last_arrival = Instant::now();
+        pipeline.push(tile.x, tile.y, tile.data.clone());
+    }
+    pipeline.close();
+    while let Some(resized) = pipeline.next().await {
+        resized?;
+    }
+    let (combined, _) = pipeline.finish().await?;
+    let latency = last_arrival.elapsed();
+    drop(combined);
+    Ok(Measurement {
+        latency,
+        peak_mb: peak_since(base),
+    })
+}
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Context as _};
+use chrono::NaiveDateTime;
+use image::{imageops, RgbImage};
+
+use crate::{
+    app::archive,
+    config::Config,
+    export::{self, pyramid},
+    framing::DiskGeometry,
+    himawari::{self, DownloadId},
+};
+#[cfg(feature = "bench")]
+use crate::{bench, himawari::Resize};
+
+const USAGE: &str = "\
+usage: himawari-pi [COMMAND]
+
+引数なしで起動するとビューアを表示します。
+
+commands:
+  equirect <INPUT> <OUTPUT> [--bounds W,E,S,N] [--resolution DEG]
+      全球画像を正距円筒図法の地図に再投影して保存する
+  geotiff <INPUT> <OUTPUT> [--size PX]
+      全球画像を静止衛星投影の座標参照系つきのGeoTIFFとして保存する
+  tiles <SOURCE> <OUTPUT_DIR> [--format xyz|dzi] [--level N]
+      画像をWebビューア向けのタイルピラミッドに分割して保存する
+      SOURCEには保存済みの画像か、撮影時刻(YYYYmmddHHMMSS、UTC)または`latest`を指定する
+      撮影時刻を指定した場合はズームレベルN(既定は20)のタイルをダウンロードして使う
+  convert
+      保存済みの画像をすべて設定(`storage.format`)の形式で保存し直す
+  compact [--days N]
+      N日(既定は`storage.bundle_after_days`)より前の画像を日ごとに1つのファイルにまとめる
+  bench [--runs N]
+      最新の画像のタイルをダウンロードし、届いたタイルから縮小する場合と、すべて届いてから縮小する場合の
+      時間とメモリをN回(既定は5回)測って比べる。`--features bench`をつけてビルドしたときだけ使える";
+
+/// サブコマンドを実行する
+pub fn run(config: &Config, args: &[String]) -> anyhow::Result<()> {
+    let Some((command, args)) = args.split_first() else {
+        bail!("{USAGE}");
+    };
+    let args = Args::parse(args)?;
+    match command.as_str() {
+        "equirect" => equirect(config, &args),
+        "geotiff" => geotiff(&args),
+        "tiles" => tiles(&args),
+        "convert" => convert(config, &args),
+        "compact" => compact(config, &args),
+        "bench" => bench(config, &args),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => bail!("unknown command: {command}\n\n{USAGE}"),
+    }
+}
+
+fn equirect(config: &Config, args: &Args) -> anyhow::Result<()> {
+    let [input, output] = args.positional()?;
+    let bounds = args.option("bounds")?.unwrap_or(config.equirect.bounds);
+    let resolution = args
+        .option("resolution")?
+        .unwrap_or(config.equirect.resolution);
+    export::equirect::check_resolution(resolution).context("invalid value for --resolution")?;
+
+    let disk = image::open(input)
+        .with_context(|| format!("failed to open {input}"))?
+        .to_rgb8();
+    let geometry = disk_geometry(input, &disk)?;
+    let map = export::equirect::reproject(&disk, &geometry, &bounds, resolution);
+    map.save(Path::new(output))
+        .with_context(|| format!("failed to save {output}"))?;
+    log::info!("Map saved: {output}");
+    Ok(())
+}
+
+fn geotiff(args: &Args) -> anyhow::Result<()> {
+    let [input, output] = args.positional()?;
+    let size = args.option::<u32>("size")?;
+    if size == Some(0) {
+        bail!("invalid value for --size: size must be a positive number");
+    }
+
+    let mut disk = image::open(input)
+        .with_context(|| format!("failed to open {input}"))?
+        .to_rgb8();
+    let mut geometry = disk_geometry(input, &disk)?;
+    if let Some(size) = size {
+        if !geometry.is_full_disk() {
+            bail!("--size can only be used with an uncropped full-disk image");
+        }
+        disk = imageops::resize(&disk, size, size, imageops::FilterType::Lanczos3);
+        geometry = DiskGeometry::full_disk(size);
+    }
+    export::geotiff::write(&disk, &geometry, Path::new(output))
+        .with_context(|| format!("failed to save {output}"))?;
+    log::info!("GeoTIFF saved: {output}");
+    Ok(())
+}
+
+/// 画像の中での全球画像の配置
+///
+/// 保存時に埋め込んだ情報があればそれを使う。なければ正方形の画像だけを切り取られていない全球画像とみなす。
+fn disk_geometry(input: &str, image: &RgbImage) -> anyhow::Result<DiskGeometry> {
+    let (width, height) = image.dimensions();
+    match archive::metadata::read_file(Path::new(input)).and_then(|metadata| metadata.disk) {
+        Some(geometry) if (geometry.width, geometry.height) == (width, height) => Ok(geometry),
+        Some(geometry) => bail!(
+            "{input} is {width}x{height}, but its disk geometry is for {}x{}",
+            geometry.width,
+            geometry.height
+        ),
+        None if width == height => Ok(DiskGeometry::full_disk(width)),
+        None => bail!("{input} is not a full-disk image: {width}x{height} without disk geometry"),
+    }
+}
+
+fn tiles(args: &Args) -> anyhow::Result<()> {
+    let [source, output] = args.positional()?;
+    let format = args.option("format")?.unwrap_or(pyramid::Format::Xyz);
+
+    let image = if Path::new(source).is_file() {
+        image::open(source)
+            .with_context(|| format!("failed to open {source}"))?
+            .to_rgb8()
+    } else {
+        let level = args.option("level")?.unwrap_or(20);
+        tokio::runtime::Runtime::new()?.block_on(async {
+            let id = match source {
+                "latest" => himawari::fetch_download_info().await?,
+                timestamp => NaiveDateTime::parse_from_str(timestamp, "%Y%m%d%H%M%S")
+                    .map(|timestamp| DownloadId::new(timestamp.and_utc()))
+                    .with_context(|| format!("no such file or invalid timestamp: {source}"))?,
+            };
+            log::info!(
+                "Download level {level} tiles of {}",
+                id.as_utc_datetime().format("%Y-%m-%d %H:%M:%S")
+            );
+            himawari::fetch_full_disk(id, level).await
+        })?
+    };
+
+    let manifest = pyramid::write(&image, Path::new(output), format)?;
+    log::info!("Tiles saved: {}", manifest.display());
+    Ok(())
+}
+
+fn convert(config: &Config, args: &Args) -> anyhow::Result<()> {
+    let [] = args.positional()?;
+    let count = tokio::runtime::Runtime::new()?.block_on(archive::convert(&config.storage))?;
+    log::info!("{count} images converted");
+    Ok(())
+}
+
+fn compact(config: &Config, args: &Args) -> anyhow::Result<()> {
+    let [] = args.positional()?;
+    let Some(days) = args.option("days")?.or(config.storage.bundle_after_days) else {
+        bail!("specify --days or storage.bundle_after_days");
+    };
+    let images =
+        tokio::runtime::Runtime::new()?.block_on(archive::compact(config.storage.layout, days))?;
+    log::info!("{} images compacted", images.len());
+    Ok(())
+}
+
+#[cfg(feature = "bench")]
+fn bench(config: &Config, args: &Args) -> anyhow::Result<()> {
+    let [] = args.positional()?;
+    let runs = args.option("runs")?.unwrap_or(5);
+    bench::pipeline(Resize::for_config(config), runs)
+}
+
+#[cfg(not(feature = "bench"))]
+fn bench(_config: &Config, _args: &Args) -> anyhow::Result<()> {
+    bail!("bench is not available: rebuild with `--features bench`")
+}
+
+/// `--name value`形式のオプションと位置引数
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut positional = vec![];
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let Some(value) = args.next() else {
+                    bail!("missing value for --{name}");
+                };
+                options.insert(name.to_string(), value.clone());
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    fn positional<const N: usize>(&self) -> anyhow::Result<[&str; N]> {
+        let args = self
+            .positional
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        args.try_into()
+            .map_err(|_| anyhow::anyhow!("expected {N} arguments\n\n{USAGE}"))
+    }
+
+    fn option<T>(&self, name: &str) -> anyhow::Result<Option<T>>
+    where
+        T: std::str::FromStr,
+        T::Err: Into<anyhow::Error>,
+    {
+        self.options
+            .get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(Into::into)
+                    .with_context(|| format!("invalid value for --{name}: {value}"))
+            })
+            .transpose()
+    }
+}
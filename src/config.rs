@@ -0,0 +1,325 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::ErrorKind,
+};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    export::{equirect, Bounds},
+    framing::{Background, Fit},
+    processing::{ResizeFilter, Stage},
+    region::Region,
+};
+
+/// `./config.json`から読み込む設定
+///
+/// ファイルが存在しない場合や項目が省略された場合は既定値を使う。
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub output: OutputConfig,
+    pub equirect: EquirectConfig,
+    pub processing: ProcessingConfig,
+    pub storage: StorageConfig,
+    pub controls: ControlsConfig,
+    pub transition: TransitionConfig,
+    pub cache: CacheConfig,
+    /// 全球画像とは別に切り出して保存する領域
+    pub regions: Vec<Region>,
+}
+
+impl Config {
+    const PATH: &'static str = "./config.json";
+
+    pub fn load() -> anyhow::Result<Self> {
+        let config: Self = match fs::read_to_string(Self::PATH) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("failed to parse {}", Self::PATH))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", Self::PATH)),
+        };
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", Self::PATH))?;
+        Ok(config)
+    }
+
+    /// 型だけでは表せない制約を確かめる
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.output.width == 0 || self.output.height == 0 {
+            anyhow::bail!(
+                "output size must not be zero: {}x{}",
+                self.output.width,
+                self.output.height
+            );
+        }
+        equirect::check_resolution(self.equirect.resolution).context("equirect.resolution")?;
+        for (i, stage) in self.processing.stages.iter().enumerate() {
+            stage
+                .check()
+                .with_context(|| format!("processing.stages[{i}]"))?;
+        }
+        // 同じ`product`の領域は同じファイルに上書きされてしまう
+        let mut products = HashSet::new();
+        for (i, region) in self.regions.iter().enumerate() {
+            region.check().with_context(|| format!("regions[{i}]"))?;
+            let product = region.product();
+            if !products.insert(product.clone()) {
+                anyhow::bail!(
+                    "region {:?} is saved as {product}, which is already used by another region",
+                    region.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 保存する画像の大きさと構図
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fit: Fit,
+    pub background: Background,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            width: 1080,
+            height: 1080,
+            fit: Fit::default(),
+            background: Background::default(),
+        }
+    }
+}
+
+/// 正距円筒図法(plate carrée)の地図の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EquirectConfig {
+    /// 画像の保存時に地図も生成して並べて保存する
+    pub auto: bool,
+    /// 出力する範囲
+    pub bounds: Bounds,
+    /// 1ピクセルあたりの経度・緯度 [deg]
+    pub resolution: f64,
+}
+
+impl Default for EquirectConfig {
+    fn default() -> Self {
+        Self {
+            auto: false,
+            bounds: Bounds::VISIBLE_HEMISPHERE,
+            resolution: 0.1,
+        }
+    }
+}
+
+/// 保存前の画像処理の設定
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessingConfig {
+    /// タイルを縮小するときのフィルタ
+    pub resize_filter: ResizeFilter,
+    /// 縮小してつなぎ合わせた画像に順番に適用する処理
+    pub stages: Vec<Stage>,
+}
+
+/// 画像の保存の設定
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub layout: Layout,
+    pub format: StorageFormat,
+    /// この日数より前の画像を日ごとに1つのファイルにまとめる。`None`ならまとめない
+    pub bundle_after_days: Option<u32>,
+    pub dedupe: DedupeConfig,
+}
+
+/// `./images`の中での画像の並べ方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// `YYYYmmddHHMMSS.png`としてすべて並べる
+    #[default]
+    Flat,
+    /// 日付(UTC)ごとのディレクトリに`YYYY/mm/dd/HHMM.png`として保存する
+    Dated,
+}
+
+/// 画像を保存する形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageFormat {
+    Png {
+        #[serde(default)]
+        compression: PngCompression,
+    },
+    /// 可逆圧縮のWebP
+    WebpLossless,
+    /// 非可逆圧縮のWebP。`quality`は0から100
+    Webp { quality: u8 },
+    /// `quality`は1から100
+    Jpeg { quality: u8 },
+}
+
+impl Default for StorageFormat {
+    fn default() -> Self {
+        StorageFormat::Png {
+            compression: PngCompression::default(),
+        }
+    }
+}
+
+/// PNGの圧縮の強さ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    /// 時間はかかるが最も小さくなる
+    Best,
+}
+
+/// 直前とほぼ同じ画像の扱い
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DedupeConfig {
+    pub mode: DedupeMode,
+    /// 知覚ハッシュ(256ビット)の異なるビットの数がこれ以下なら重複とみなす
+    pub threshold: u32,
+}
+
+impl Default for DedupeConfig {
+    fn default() -> Self {
+        Self {
+            mode: DedupeMode::default(),
+            threshold: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupeMode {
+    /// 判定しない
+    Off,
+    /// 保存して記録だけ残す
+    #[default]
+    Flag,
+    /// 保存しない
+    Skip,
+}
+
+/// 画面の操作の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ControlsConfig {
+    /// キーの名前(icedの`KeyCode`の名前)やジェスチャーの名前(`SwipeLeft`, `SwipeRight`, `Tap`, `DoubleTap`, `LongPress`)と操作の対応。
+    /// 指定するとすべて置き換わる
+    pub keymap: BTreeMap<String, Action>,
+    /// 再生するときの1枚あたりの表示時間 [ms]
+    pub playback_interval_ms: u64,
+}
+
+impl Default for ControlsConfig {
+    fn default() -> Self {
+        let keymap = [
+            ("Left", Action::Previous),
+            ("Right", Action::Next),
+            ("Home", Action::Oldest),
+            ("End", Action::Newest),
+            ("Space", Action::TogglePlayback),
+            ("Escape", Action::CloseMenu),
+            ("SwipeLeft", Action::Next),
+            ("SwipeRight", Action::Previous),
+            ("DoubleTap", Action::ResetZoom),
+            ("LongPress", Action::OpenMenu),
+        ];
+        Self {
+            keymap: keymap
+                .into_iter()
+                .map(|(key, action)| (key.to_string(), action))
+                .collect(),
+            playback_interval_ms: 250,
+        }
+    }
+}
+
+impl ControlsConfig {
+    /// キーやジェスチャーの名前に割り当てた操作
+    pub fn action(&self, name: &str) -> Option<Action> {
+        self.keymap.get(name).copied()
+    }
+}
+
+/// メイン画面で画像を切り替えるときのクロスフェードの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TransitionConfig {
+    /// クロスフェードにかける時間 [ms]。`0`なら切り替えるだけ。再生中はクロスフェードしない。
+    /// 途中の画像の合成がこの時間のうちに終わらなければ、クロスフェードせずに切り替える
+    pub duration_ms: u64,
+    /// あらかじめ合成しておく途中の画像の枚数。多いほど滑らかになるが、合成とメモリの負担が増える
+    pub frames: u32,
+    /// 雲の動きを推定して、動かしながら重ねる
+    pub interpolate: bool,
+}
+
+impl Default for TransitionConfig {
+    fn default() -> Self {
+        Self {
+            duration_ms: 300,
+            frames: 6,
+            interpolate: false,
+        }
+    }
+}
+
+/// メイン画面に表示する画像をあらかじめ展開しておくキャッシュの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// 展開した画像に使うメモリの上限 [MB]。1080x1080の画像は1枚あたり約4.5MB
+    pub memory_mb: usize,
+    /// 表示中の画像から進む向きに先読みする枚数
+    pub prefetch: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            memory_mb: 64,
+            prefetch: 4,
+        }
+    }
+}
+
+/// キーやスワイプに割り当てる操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// 1つ前の画像
+    Previous,
+    /// 1つ後の画像
+    Next,
+    /// 一番古い画像
+    Oldest,
+    /// 一番新しい画像
+    Newest,
+    /// 直近24時間の画像の再生・停止
+    TogglePlayback,
+    /// 画像の一覧を開く
+    OpenMenu,
+    /// 画像の一覧を閉じる
+    CloseMenu,
+    /// 拡大をやめて画像全体を表示する
+    ResetZoom,
+}
@@ -0,0 +1,64 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _};
+use serde::Deserialize;
+
+pub mod equirect;
+pub mod geotiff;
+pub mod pyramid;
+
+/// 緯度経度で表した矩形範囲 [deg]
+///
+/// `east < west`の場合は日付変更線をまたぐ範囲として扱う。
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Bounds {
+    pub west: f64,
+    pub east: f64,
+    pub south: f64,
+    pub north: f64,
+}
+
+impl Bounds {
+    /// ひまわり9号から見える半球全体
+    pub const VISIBLE_HEMISPHERE: Bounds = Bounds {
+        west: 60.7,
+        east: -139.3,
+        south: -81.3,
+        north: 81.3,
+    };
+
+    /// 経度方向の幅 [deg]
+    pub fn width(&self) -> f64 {
+        (self.east - self.west).rem_euclid(360.0)
+    }
+
+    /// 緯度方向の高さ [deg]
+    pub fn height(&self) -> f64 {
+        self.north - self.south
+    }
+}
+
+impl FromStr for Bounds {
+    type Err = anyhow::Error;
+
+    /// `west,east,south,north`の形式で読む
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid bounds: {s}"))?;
+        let [west, east, south, north] = values[..] else {
+            bail!("bounds must be `west,east,south,north`: {s}");
+        };
+        if south >= north {
+            bail!("south must be less than north: {s}");
+        }
+        Ok(Bounds {
+            west,
+            east,
+            south,
+            north,
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use image::{imageops, RgbImage, Rgba, RgbaImage};
+use rayon::{
+    prelude::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+
+use crate::framing::DiskGeometry;
+
+use super::Bounds;
+
+/// 1ピクセルあたりの経度・緯度 [deg]として使えるか確かめる
+pub fn check_resolution(resolution: f64) -> anyhow::Result<()> {
+    if !(resolution.is_finite() && resolution > 0.0) {
+        anyhow::bail!("resolution must be a positive number: {resolution}");
+    }
+    Ok(())
+}
+
+/// 全球画像を正距円筒図法(plate carrée)の地図に再投影する
+///
+/// `disk`の中での全球画像の配置は`geometry`で指定する。切り取られた全球画像でもよい。
+/// 地球の外側や範囲外、`disk`に写っていない地点の画素は透明になる。
+/// `resolution`は`check_resolution`で確かめておく。
+pub fn reproject(
+    disk: &RgbImage,
+    geometry: &DiskGeometry,
+    bounds: &Bounds,
+    resolution: f64,
+) -> RgbaImage {
+    let width = (bounds.width() / resolution).round().max(1.0) as u32;
+    let height = (bounds.height() / resolution).round().max(1.0) as u32;
+    let projection = geometry.projection();
+
+    let mut map = RgbaImage::new(width, height);
+    map.par_chunks_exact_mut(width as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let lat = bounds.north - (y as f64 + 0.5) * resolution;
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let lon = bounds.west + (x as f64 + 0.5) * resolution;
+                let sample = projection.lonlat_to_pixel(lon, lat).and_then(|(px, py)| {
+                    let (px, py) = geometry.output_position(px, py);
+                    // 画素の中心を原点とする座標に直して補間する
+                    imageops::interpolate_bilinear(disk, px as f32 - 0.5, py as f32 - 0.5)
+                });
+                if let Some(rgb) = sample {
+                    pixel.copy_from_slice(&Rgba([rgb[0], rgb[1], rgb[2], 0xff]).0);
+                }
+            }
+        });
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use crate::framing::{self, Background, Fit};
+
+    use super::*;
+
+    #[test]
+    fn reprojects_cover_framed_composite() {
+        let size = 240;
+        let disk = RgbImage::from_fn(size, size, |x, y| Rgb([x as u8, y as u8, 0]));
+        // 上下を切り取った横長の画像
+        let geometry = DiskGeometry::centered(Fit::Cover, size, 240, 120);
+        let composite = framing::compose(&disk, &geometry, &Background::default())
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(composite.dimensions(), (240, 120));
+
+        let (bounds, resolution) = (Bounds::VISIBLE_HEMISPHERE, 1.0);
+        let expected = reproject(&disk, &DiskGeometry::full_disk(size), &bounds, resolution);
+        let map = reproject(&composite, &geometry, &bounds, resolution);
+        assert_eq!(map.dimensions(), expected.dimensions());
+
+        let projection = geometry.projection();
+        let center = size as f64 / 2.0;
+        let (mut compared, mut cropped) = (0, 0);
+        for (x, y, pixel) in map.enumerate_pixels() {
+            let lon = bounds.west + (x as f64 + 0.5) * resolution;
+            let lat = bounds.north - (y as f64 + 0.5) * resolution;
+            let Some((px, py)) = projection.lonlat_to_pixel(lon, lat) else {
+                assert_eq!(pixel[3], 0);
+                continue;
+            };
+            // 背景と混ざる地球の縁は比べない
+            if (px - center).hypot(py - center) > projection.limb_radius() - 2.0 {
+                continue;
+            }
+            let (_, output_y) = geometry.output_position(px, py);
+            if !(-1.0..=121.0).contains(&output_y) {
+                assert_eq!(pixel[3], 0, "cropped point ({lon}, {lat}) is drawn");
+                cropped += 1;
+            } else if (0.5..=119.5).contains(&output_y) {
+                let expected = expected.get_pixel(x, y);
+                assert_eq!(pixel[3], 0xff);
+                for i in 0..3 {
+                    assert!(
+                        pixel[i].abs_diff(expected[i]) <= 1,
+                        "{pixel:?} != {expected:?}"
+                    );
+                }
+                compared += 1;
+            }
+        }
+        assert!(compared > 1000 && cropped > 1000, "{compared}, {cropped}");
+    }
+}
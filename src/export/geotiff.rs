@@ -0,0 +1,249 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use image::RgbImage;
+use tiff::{
+    encoder::{colortype, compression::Deflate, TiffEncoder},
+    tags::Tag,
+};
+
+use crate::{
+    framing::DiskGeometry,
+    himawari::projection::{
+        EQUATORIAL_RADIUS, FULL_DISK_EXTENT, POLAR_RADIUS, SATELLITE_HEIGHT, SUB_LONGITUDE,
+    },
+};
+
+/// GeoTIFFの「ユーザー定義」を表す値
+const USER_DEFINED: u16 = 32767;
+
+/// 全球画像を静止衛星投影の座標参照系つきのGeoTIFFとして保存する
+///
+/// `disk`の中での全球画像の配置は`geometry`で指定する。切り取られた全球画像でもよい。
+/// 静止衛星投影はGeoTIFFのGeoKeyでは表現できないので、GDALと同じく
+/// `PCSCitationGeoKey`にWKTを埋め込む。
+pub fn write(disk: &RgbImage, geometry: &DiskGeometry, path: &Path) -> anyhow::Result<()> {
+    let pixel_size = 2.0 * FULL_DISK_EXTENT / geometry.size as f64;
+    // 画像の左上の投影座標
+    let origin_x = -FULL_DISK_EXTENT - geometry.left * pixel_size;
+    let origin_y = FULL_DISK_EXTENT + geometry.top * pixel_size;
+    let geo_keys = GeoKeys::new();
+
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+    let mut image = encoder.new_image_with_compression::<colortype::RGB8, _>(
+        disk.width(),
+        disk.height(),
+        Deflate::default(),
+    )?;
+    let directory = image.encoder();
+    directory.write_tag(
+        Tag::Software,
+        concat!("himawari-pi ", env!("CARGO_PKG_VERSION")),
+    )?;
+    directory.write_tag(Tag::ModelPixelScaleTag, &[pixel_size, pixel_size, 0.0][..])?;
+    directory.write_tag(
+        Tag::ModelTiepointTag,
+        &[0.0, 0.0, 0.0, origin_x, origin_y, 0.0][..],
+    )?;
+    directory.write_tag(Tag::GeoKeyDirectoryTag, &geo_keys.directory[..])?;
+    directory.write_tag(Tag::GeoDoubleParamsTag, &geo_keys.doubles[..])?;
+    directory.write_tag(Tag::GeoAsciiParamsTag, geo_keys.ascii.as_str())?;
+    image.write_data(disk.as_raw())?;
+    Ok(())
+}
+
+/// `+proj=geos`相当のWKT(GDALのWKT1形式)
+fn wkt() -> String {
+    format!(
+        concat!(
+            r#"PROJCS["Himawari-9 full disk","#,
+            r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",{a},{inv_f}]],"#,
+            r#"PRIMEM["Greenwich",0],UNIT["degree",0.0174532925199433]],"#,
+            r#"PROJECTION["Geostationary_Satellite"],"#,
+            r#"PARAMETER["central_meridian",{lon}],PARAMETER["satellite_height",{h}],"#,
+            r#"PARAMETER["false_easting",0],PARAMETER["false_northing",0],"#,
+            r#"UNIT["metre",1],EXTENSION["PROJ4","{proj4}"]]"#,
+        ),
+        a = EQUATORIAL_RADIUS,
+        inv_f = EQUATORIAL_RADIUS / (EQUATORIAL_RADIUS - POLAR_RADIUS),
+        lon = SUB_LONGITUDE,
+        h = SATELLITE_HEIGHT,
+        proj4 = proj4(),
+    )
+}
+
+fn proj4() -> String {
+    format!(
+        "+proj=geos +lon_0={SUB_LONGITUDE} +h={SATELLITE_HEIGHT} \
+         +a={EQUATORIAL_RADIUS} +b={POLAR_RADIUS} +sweep=y +units=m +no_defs"
+    )
+}
+
+/// GeoKeyDirectoryTagとそこから参照されるパラメータ
+struct GeoKeys {
+    directory: Vec<u16>,
+    doubles: Vec<f64>,
+    ascii: String,
+}
+
+impl GeoKeys {
+    fn new() -> Self {
+        let mut keys = GeoKeys {
+            // KeyDirectoryVersion, KeyRevision, MinorRevision, NumberOfKeys
+            directory: vec![1, 1, 0, 0],
+            doubles: vec![],
+            ascii: String::new(),
+        };
+        // キーはID順に並べる必要がある
+        keys.short(1024, 1); // GTModelTypeGeoKey = ModelTypeProjected
+        keys.short(1025, 1); // GTRasterTypeGeoKey = RasterPixelIsArea
+        keys.ascii(1026, "Himawari-9 AHI full disk"); // GTCitationGeoKey
+        keys.short(2048, USER_DEFINED); // GeographicTypeGeoKey
+        keys.ascii(2049, "WGS 84"); // GeogCitationGeoKey
+        keys.short(2050, 6326); // GeogGeodeticDatumGeoKey = WGS 84
+        keys.short(2054, 9102); // GeogAngularUnitsGeoKey = degree
+        keys.double(2057, EQUATORIAL_RADIUS); // GeogSemiMajorAxisGeoKey
+        keys.double(2058, POLAR_RADIUS); // GeogSemiMinorAxisGeoKey
+        keys.short(3072, USER_DEFINED); // ProjectedCSTypeGeoKey
+        keys.ascii(3073, &format!("ESRI PE String = {}", wkt())); // PCSCitationGeoKey
+        keys.short(3074, USER_DEFINED); // ProjectionGeoKey
+        keys.short(3075, USER_DEFINED); // ProjCoordTransGeoKey
+        keys.short(3076, 9001); // ProjLinearUnitsGeoKey = metre
+        keys.double(3082, 0.0); // ProjFalseEastingGeoKey
+        keys.double(3083, 0.0); // ProjFalseNorthingGeoKey
+        keys.double(3088, SUB_LONGITUDE); // ProjCenterLongGeoKey
+        keys
+    }
+
+    fn push(&mut self, id: u16, location: u16, count: u16, value: u16) {
+        self.directory.extend([id, location, count, value]);
+        self.directory[3] += 1;
+    }
+
+    fn short(&mut self, id: u16, value: u16) {
+        self.push(id, 0, 1, value);
+    }
+
+    fn double(&mut self, id: u16, value: f64) {
+        let offset = self.doubles.len() as u16;
+        self.doubles.push(value);
+        self.push(id, Tag::GeoDoubleParamsTag.to_u16(), 1, offset);
+    }
+
+    fn ascii(&mut self, id: u16, value: &str) {
+        let offset = self.ascii.len() as u16;
+        // 各文字列は`|`で終端する
+        self.ascii.push_str(value);
+        self.ascii.push('|');
+        self.push(
+            id,
+            Tag::GeoAsciiParamsTag.to_u16(),
+            value.len() as u16 + 1,
+            offset,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    use crate::framing::Fit;
+
+    use super::*;
+
+    /// GeoKeyDirectoryTagから`id`のキーの(場所, 数, 値)を探す
+    fn geo_key(directory: &[u16], id: u16) -> Option<(u16, u16, u16)> {
+        directory[4..]
+            .chunks_exact(4)
+            .find(|key| key[0] == id)
+            .map(|key| (key[1], key[2], key[3]))
+    }
+
+    #[test]
+    fn round_trip() {
+        let disk = RgbImage::from_fn(8, 8, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        let path = std::env::temp_dir().join(format!("himawari-pi-{}.tif", std::process::id()));
+        write(&disk, &DiskGeometry::full_disk(8), &path).unwrap();
+        let mut decoder = Decoder::new(File::open(&path).unwrap()).unwrap();
+
+        assert_eq!(decoder.dimensions().unwrap(), (8, 8));
+        let DecodingResult::U8(pixels) = decoder.read_image().unwrap() else {
+            panic!("unexpected sample type");
+        };
+        assert_eq!(pixels, disk.into_raw());
+
+        let pixel_size = 2.0 * FULL_DISK_EXTENT / 8.0;
+        let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag).unwrap();
+        assert_eq!(scale, [pixel_size, pixel_size, 0.0]);
+        let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).unwrap();
+        assert_eq!(
+            tiepoint,
+            [0.0, 0.0, 0.0, -FULL_DISK_EXTENT, FULL_DISK_EXTENT, 0.0]
+        );
+
+        let directory = decoder.get_tag_u16_vec(Tag::GeoKeyDirectoryTag).unwrap();
+        assert_eq!(directory[..3], [1, 1, 0]);
+        assert_eq!(directory.len(), 4 + directory[3] as usize * 4);
+        let ids = directory[4..]
+            .chunks_exact(4)
+            .map(|key| key[0])
+            .collect::<Vec<_>>();
+        assert!(ids.windows(2).all(|ids| ids[0] < ids[1]));
+        assert_eq!(geo_key(&directory, 1024), Some((0, 1, 1)));
+        assert_eq!(geo_key(&directory, 1025), Some((0, 1, 1)));
+        assert_eq!(geo_key(&directory, 3072), Some((0, 1, USER_DEFINED)));
+        assert_eq!(geo_key(&directory, 3076), Some((0, 1, 9001)));
+
+        let doubles = decoder.get_tag_f64_vec(Tag::GeoDoubleParamsTag).unwrap();
+        let double = |id| {
+            let (location, count, offset) = geo_key(&directory, id).unwrap();
+            assert_eq!((location, count), (Tag::GeoDoubleParamsTag.to_u16(), 1));
+            doubles[offset as usize]
+        };
+        assert_eq!(double(2057), EQUATORIAL_RADIUS);
+        assert_eq!(double(2058), POLAR_RADIUS);
+        assert_eq!(double(3088), SUB_LONGITUDE);
+
+        let ascii = decoder
+            .get_tag_ascii_string(Tag::GeoAsciiParamsTag)
+            .unwrap();
+        let (location, count, offset) = geo_key(&directory, 3073).unwrap();
+        assert_eq!(location, Tag::GeoAsciiParamsTag.to_u16());
+        let citation = &ascii[offset as usize..(offset + count) as usize];
+        assert!(citation.starts_with("ESRI PE String = PROJCS["));
+        assert!(citation.contains(r#"PROJECTION["Geostationary_Satellite"]"#));
+        assert!(citation.ends_with('|'));
+
+        drop(decoder);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tiepoint_follows_cropped_disk() {
+        // 一辺8pxの全球画像の上下2pxずつを切り取った画像
+        let disk = RgbImage::new(8, 4);
+        let geometry = DiskGeometry::centered(Fit::Cover, 8, 8, 4);
+        let path =
+            std::env::temp_dir().join(format!("himawari-pi-{}-crop.tif", std::process::id()));
+        write(&disk, &geometry, &path).unwrap();
+        let mut decoder = Decoder::new(File::open(&path).unwrap()).unwrap();
+
+        assert_eq!(decoder.dimensions().unwrap(), (8, 4));
+        let pixel_size = 2.0 * FULL_DISK_EXTENT / 8.0;
+        let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).unwrap();
+        assert_eq!(
+            tiepoint,
+            [
+                0.0,
+                0.0,
+                0.0,
+                -FULL_DISK_EXTENT,
+                FULL_DISK_EXTENT - 2.0 * pixel_size,
+                0.0
+            ]
+        );
+
+        drop(decoder);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
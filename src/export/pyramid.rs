@@ -0,0 +1,175 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::bail;
+use image::{imageops, RgbImage};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+
+/// タイルピラミッドの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `{z}/{x}/{y}.png`のディレクトリ構成(Leafletなど)
+    Xyz,
+    /// Deep Zoom Image(OpenSeadragonなど)
+    Dzi,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xyz" => Ok(Format::Xyz),
+            "dzi" => Ok(Format::Dzi),
+            _ => bail!("unknown format: {s} (available: xyz, dzi)"),
+        }
+    }
+}
+
+/// XYZ形式のタイルの大きさ [px]
+const XYZ_TILE_SIZE: u32 = 256;
+/// DZI形式のタイルの大きさ [px]
+const DZI_TILE_SIZE: u32 = 254;
+/// DZI形式で隣のタイルと重ねる幅 [px]
+const DZI_OVERLAP: u32 = 1;
+
+/// 画像を複数のズームレベルのタイルに分割して`dir`に書き出す
+///
+/// 書き出したタイルの一覧は、XYZ形式では`tiles.json`、DZI形式では`image.dzi`に記録する。
+pub fn write(image: &RgbImage, dir: &Path, format: Format) -> anyhow::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    match format {
+        Format::Xyz => write_xyz(image, dir),
+        Format::Dzi => write_dzi(image, dir),
+    }
+}
+
+#[derive(Serialize)]
+struct XyzManifest {
+    format: &'static str,
+    template: &'static str,
+    tile_size: u32,
+    min_zoom: u32,
+    max_zoom: u32,
+    /// 最大ズームレベルでの元画像の大きさ。ここより外側のタイルは存在しない
+    width: u32,
+    height: u32,
+}
+
+fn write_xyz(image: &RgbImage, dir: &Path) -> anyhow::Result<PathBuf> {
+    let (width, height) = image.dimensions();
+    let max_zoom = levels_for(width.max(height).div_ceil(XYZ_TILE_SIZE));
+
+    // 最大ズームレベルでは拡大せず、左上に寄せて2の累乗の大きさに余白を足す
+    let mut level = image.clone();
+    let (mut level_width, mut level_height) = (width, height);
+    for z in (0..=max_zoom).rev() {
+        if z < max_zoom {
+            level_width = level_width.div_ceil(2);
+            level_height = level_height.div_ceil(2);
+            level = imageops::resize(
+                &level,
+                level_width,
+                level_height,
+                imageops::FilterType::Triangle,
+            );
+        }
+        let columns = level_width.div_ceil(XYZ_TILE_SIZE);
+        let rows = level_height.div_ceil(XYZ_TILE_SIZE);
+        (0..columns).into_par_iter().try_for_each(|x| {
+            let column_dir = dir.join(format!("{z}/{x}"));
+            fs::create_dir_all(&column_dir)?;
+            for y in 0..rows {
+                let mut tile = RgbImage::new(XYZ_TILE_SIZE, XYZ_TILE_SIZE);
+                let view = imageops::crop_imm(
+                    &level,
+                    x * XYZ_TILE_SIZE,
+                    y * XYZ_TILE_SIZE,
+                    XYZ_TILE_SIZE,
+                    XYZ_TILE_SIZE,
+                );
+                imageops::replace(&mut tile, &*view, 0, 0);
+                tile.save(column_dir.join(format!("{y}.png")))?;
+            }
+            anyhow::Ok(())
+        })?;
+        log::info!("Zoom level {z}: {columns}x{rows} tiles");
+    }
+
+    let manifest_path = dir.join("tiles.json");
+    let manifest = XyzManifest {
+        format: "xyz",
+        template: "{z}/{x}/{y}.png",
+        tile_size: XYZ_TILE_SIZE,
+        min_zoom: 0,
+        max_zoom,
+        width,
+        height,
+    };
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(manifest_path)
+}
+
+fn write_dzi(image: &RgbImage, dir: &Path) -> anyhow::Result<PathBuf> {
+    let (width, height) = image.dimensions();
+    let max_level = levels_for(width.max(height));
+    let files_dir = dir.join("image_files");
+
+    let mut level = image.clone();
+    for l in (0..=max_level).rev() {
+        if l < max_level {
+            let (w, h) = level.dimensions();
+            level = imageops::resize(
+                &level,
+                w.div_ceil(2),
+                h.div_ceil(2),
+                imageops::FilterType::Triangle,
+            );
+        }
+        let (level_width, level_height) = level.dimensions();
+        let columns = level_width.div_ceil(DZI_TILE_SIZE);
+        let rows = level_height.div_ceil(DZI_TILE_SIZE);
+        let level_dir = files_dir.join(l.to_string());
+        fs::create_dir_all(&level_dir)?;
+        (0..columns).into_par_iter().try_for_each(|column| {
+            for row in 0..rows {
+                let x = (column * DZI_TILE_SIZE).saturating_sub(DZI_OVERLAP);
+                let y = (row * DZI_TILE_SIZE).saturating_sub(DZI_OVERLAP);
+                let right = ((column + 1) * DZI_TILE_SIZE + DZI_OVERLAP).min(level_width);
+                let bottom = ((row + 1) * DZI_TILE_SIZE + DZI_OVERLAP).min(level_height);
+                imageops::crop_imm(&level, x, y, right - x, bottom - y)
+                    .to_image()
+                    .save(level_dir.join(format!("{column}_{row}.png")))?;
+            }
+            anyhow::Ok(())
+        })?;
+        log::info!("Level {l}: {columns}x{rows} tiles");
+    }
+
+    let manifest_path = dir.join("image.dzi");
+    let manifest = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "\n",
+            r#"<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" Format="png" Overlap="{overlap}" TileSize="{tile_size}">"#,
+            "\n",
+            r#"  <Size Width="{width}" Height="{height}"/>"#,
+            "\n</Image>\n",
+        ),
+        overlap = DZI_OVERLAP,
+        tile_size = DZI_TILE_SIZE,
+        width = width,
+        height = height,
+    );
+    fs::write(&manifest_path, manifest)?;
+    Ok(manifest_path)
+}
+
+/// `size`を1になるまで半分にし続けるのに必要な回数
+fn levels_for(size: u32) -> u32 {
+    size.max(1).next_power_of_two().trailing_zeros()
+}
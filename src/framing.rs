@@ -0,0 +1,181 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use image::{imageops, DynamicImage, RgbImage, Rgba, RgbaImage};
+use rayon::{
+    prelude::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::himawari::FullDisk;
+
+/// 全球画像を出力画像に収める方法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fit {
+    /// 全球画像全体が収まるように縮小し、余白を背景で埋める
+    #[default]
+    Contain,
+    /// 出力画像全体が全球画像で覆われるように拡大し、はみ出した部分を切り取る
+    Cover,
+    /// 宇宙の余白を切り取り、地球がちょうど収まるようにする
+    Fill,
+}
+
+/// 地球の外側の背景
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Background {
+    /// 単色
+    Solid { color: [u8; 3] },
+    /// 上から下へのグラデーション
+    Gradient { top: [u8; 3], bottom: [u8; 3] },
+    /// 画像(星空など)。出力画像を覆うように拡大・縮小して使う
+    Image { path: PathBuf },
+    /// 透明。地球を円形に切り抜いた画像になる
+    Transparent,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Solid { color: [0, 0, 0] }
+    }
+}
+
+impl Background {
+    fn render(&self, width: u32, height: u32) -> anyhow::Result<RgbaImage> {
+        let background = match self {
+            Background::Solid { color: [r, g, b] } => {
+                RgbaImage::from_pixel(width, height, Rgba([*r, *g, *b, 0xff]))
+            }
+            Background::Gradient { top, bottom } => RgbaImage::from_fn(width, height, |_, y| {
+                let t = y as f32 / height.saturating_sub(1).max(1) as f32;
+                let [r, g, b] = std::array::from_fn(|i| {
+                    (top[i] as f32 * (1.0 - t) + bottom[i] as f32 * t).round() as u8
+                });
+                Rgba([r, g, b, 0xff])
+            }),
+            Background::Image { path } => {
+                let image = image::open(path)
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                image
+                    .resize_to_fill(width, height, imageops::FilterType::Triangle)
+                    .to_rgba8()
+            }
+            Background::Transparent => RgbaImage::new(width, height),
+        };
+        Ok(background)
+    }
+}
+
+/// 出力画像の中での全球画像の配置
+///
+/// 保存した画像に埋め込んでおき、地図への再投影や拡大表示で画素と全球画像の位置を対応させる。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DiskGeometry {
+    pub fit: Fit,
+    /// 出力画像の大きさ [px]
+    pub width: u32,
+    pub height: u32,
+    /// 全球画像の一辺 [px]
+    pub size: u32,
+    /// 出力画像の左上から全球画像の左上までの距離 [px]。はみ出して切り取られた場合は負になる
+    pub left: f64,
+    pub top: f64,
+}
+
+impl DiskGeometry {
+    /// 一辺`size`の全球画像を`width`x`height`の出力画像の中央に置く
+    pub fn centered(fit: Fit, size: u32, width: u32, height: u32) -> Self {
+        Self {
+            fit,
+            width,
+            height,
+            size,
+            left: (width as f64 - size as f64) / 2.0,
+            top: (height as f64 - size as f64) / 2.0,
+        }
+    }
+
+    /// 一辺`size`の全球画像をそのまま使う
+    pub fn full_disk(size: u32) -> Self {
+        Self::centered(Fit::Contain, size, size, size)
+    }
+
+    /// 全球画像を切り取らず、余白も足さずにそのまま使っているか
+    pub fn is_full_disk(&self) -> bool {
+        self.width == self.size && self.height == self.size && self.left == 0.0 && self.top == 0.0
+    }
+
+    /// 全球画像の画素座標と緯度経度の変換。出力画像の画素座標には`output_position`で直す
+    pub fn projection(&self) -> FullDisk {
+        FullDisk::new(self.size)
+    }
+
+    /// 全球画像の画素座標を出力画像の画素座標に直す
+    pub fn output_position(&self, x: f64, y: f64) -> (f64, f64) {
+        (x + self.left, y + self.top)
+    }
+}
+
+/// `width`x`height`の出力画像を作るのに必要な全球画像の大きさ [px]
+pub fn disk_size(fit: Fit, width: u32, height: u32) -> u32 {
+    match fit {
+        Fit::Contain => width.min(height),
+        Fit::Cover => width.max(height),
+        Fit::Fill => {
+            // 地球の直径が出力画像の短辺に一致する大きさ
+            let limb_ratio = 2.0 * FullDisk::new(1).limb_radius();
+            (width.min(height) as f64 / limb_ratio).ceil() as u32
+        }
+    }
+}
+
+/// 全球画像を`geometry`のとおりに配置し、地球の外側を背景で置き換える
+///
+/// 背景が透明の場合はアルファチャンネルつきの画像を返す。
+pub fn compose(
+    disk: &RgbImage,
+    geometry: &DiskGeometry,
+    background: &Background,
+) -> anyhow::Result<DynamicImage> {
+    let (width, height) = (geometry.width, geometry.height);
+    let mut output = background.render(width, height)?;
+
+    let size = disk.width();
+    let (offset_x, offset_y) = (geometry.left, geometry.top);
+    let center = size as f64 / 2.0;
+    // 大気の光が残るように少しだけ外側で切り抜く
+    let radius = FullDisk::new(size).limb_radius() * 1.005;
+
+    output
+        .par_chunks_exact_mut(width as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let dy = y as f64 + 0.5 - offset_y - center;
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let dx = x as f64 + 0.5 - offset_x - center;
+                // 縁の1ピクセルはアンチエイリアスする
+                let alpha = (radius + 0.5 - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0);
+                if alpha == 0.0 {
+                    continue;
+                }
+                let (disk_x, disk_y) = ((dx + center) as u32, (dy + center) as u32);
+                let disk_pixel = disk.get_pixel(disk_x.min(size - 1), disk_y.min(size - 1));
+                // 背景の上に重ねる(over合成)
+                let background_alpha = pixel[3] as f64 / 255.0 * (1.0 - alpha);
+                let output_alpha = alpha + background_alpha;
+                for i in 0..3 {
+                    let blended = disk_pixel[i] as f64 * alpha + pixel[i] as f64 * background_alpha;
+                    pixel[i] = (blended / output_alpha).round() as u8;
+                }
+                pixel[3] = (output_alpha * 255.0).round() as u8;
+            }
+        });
+
+    Ok(match background {
+        Background::Transparent => DynamicImage::ImageRgba8(output),
+        _ => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(output).to_rgb8()),
+    })
+}
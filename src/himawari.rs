@@ -1,14 +1,27 @@
 use chrono::{DateTime, Local, Utc};
 mod download;
 mod fetch;
+mod full_disk;
+mod pipeline;
+pub mod projection;
 
-pub use download::{download_subscription, Progress};
+pub use download::{download_subscription, Progress, Tiles, LEVEL as DOWNLOAD_LEVEL};
 pub use fetch::fetch_download_info;
+pub use full_disk::{fetch_full_disk, fetch_tiles, LEVELS};
+pub use pipeline::Resize;
+#[cfg(feature = "bench")]
+pub use pipeline::TilePipeline;
+pub use projection::FullDisk;
 
 const LATEST_JSON_URL: &str = "https://himawari.asia/img/FULL_24h/latest.json";
-const IMAGE_BASE_URL: &str = "https://himawari.asia/img/D531106/2d/550";
+const IMAGE_BASE_URL: &str = "https://himawari.asia/img/D531106";
+/// タイル1枚の大きさ [px]
+pub const TILE_SIZE: u32 = 550;
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
 pub struct DownloadId(DateTime<Utc>);
 
 impl DownloadId {
@@ -23,4 +36,10 @@ impl DownloadId {
     pub fn as_local_datetime(&self) -> DateTime<Local> {
         DateTime::from(self.0)
     }
+
+    /// ズームレベル`level`で左から`x`番目、上から`y`番目のタイルのURL
+    pub fn tile_url(&self, level: u32, x: u32, y: u32) -> String {
+        let path = self.0.format("%Y/%m/%d/%H%M%S");
+        format!("{IMAGE_BASE_URL}/{level}d/{TILE_SIZE}/{path}_{x}_{y}.png")
+    }
 }
@@ -1,25 +1,76 @@
-use std::sync::Arc;
+use std::{
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context as _;
+use bytes::Bytes;
 use futures::{future::try_join_all, stream::FuturesUnordered, FutureExt, StreamExt};
-use iced::{subscription, Subscription};
+use iced::{subscription, widget::image::Handle, Subscription};
+use image::RgbImage;
 use reqwest::{Client, Response};
 
-use super::{DownloadId, IMAGE_BASE_URL};
+use super::{
+    pipeline::{Resize, ResizedTile, SourceTile, TilePipeline},
+    DownloadId,
+};
+
+/// ダウンロードするタイルのズームレベル
+pub const LEVEL: u32 = 2;
+/// ダウンロードするタイルの位置`(x, y)`
+const TILE_POSITIONS: [(u32, u32); 4] = [(0, 0), (0, 1), (1, 0), (1, 1)];
 
 #[derive(Debug, Clone)]
 pub enum Progress {
     Started,
     Advanced(f32),
-    Finished([Vec<u8>; 4]),
+    /// タイルを1枚ダウンロードして縮小し終わった。`x`, `y`はタイルの位置
+    TileFinished {
+        x: u32,
+        y: u32,
+        /// 地球の外側を透明にしたタイル
+        tile: Handle,
+    },
+    Finished(Box<Tiles>),
     Failed(Arc<anyhow::Error>),
 }
 
-pub fn download_subscription(id: DownloadId) -> Subscription<(DownloadId, Progress)> {
-    subscription::unfold(id, State::Ready(id), move |state| download(id, state))
+/// ダウンロードして縮小した4枚のタイル
+#[derive(Debug, Clone)]
+pub struct Tiles {
+    /// 縮小したタイルをつなぎ合わせた全球画像
+    pub image: RgbImage,
+    /// 縮小する前のタイル
+    pub sources: Vec<SourceTile>,
+    /// ズームレベル
+    pub level: u32,
+    pub urls: [String; 4],
+    /// ダウンロードにかかった時間
+    pub duration: Duration,
+}
+
+/// ダウンロードしながら、終わったタイルから`resize`の大きさに縮小する
+pub fn download_subscription(
+    id: DownloadId,
+    resize: Resize,
+) -> Subscription<(DownloadId, Progress)> {
+    subscription::unfold(id, State::Ready(id), move |state| {
+        download(id, resize, state)
+    })
+}
+
+/// ダウンロード中に次に起きたこと
+enum Event {
+    Chunk(usize, reqwest::Result<Option<Bytes>>),
+    Tile(Option<anyhow::Result<ResizedTile>>),
 }
 
-async fn download(timestamp: DownloadId, state: State) -> ((DownloadId, Progress), State) {
+async fn download(
+    timestamp: DownloadId,
+    resize: Resize,
+    state: State,
+) -> ((DownloadId, Progress), State) {
     match state {
         State::Ready(id) => {
             let items = match get_download_items(&id).await {
@@ -29,60 +80,100 @@ async fn download(timestamp: DownloadId, state: State) -> ((DownloadId, Progress
                 }
             };
             log::info!("Start downloading");
-            ((timestamp, Progress::Started), State::Downloading(items))
+            (
+                (timestamp, Progress::Started),
+                State::Downloading {
+                    items: Box::new(items),
+                    pipeline: Box::new(TilePipeline::new(resize, 2, 2)),
+                    started_at: Instant::now(),
+                },
+            )
         }
-        State::Downloading(mut items) => {
-            let first_result = {
-                // 未完了のダウンロードのchunkをFuturesUnorderedで並行実行し、最初に返ってきたものをnext()で取得する
-                items
+        State::Downloading {
+            mut items,
+            mut pipeline,
+            started_at,
+        } => {
+            let event = if items.iter().all(|item| item.is_finished) {
+                // 残りのタイルが縮小し終わるのを待つ
+                pipeline.close();
+                Event::Tile(pipeline.next().await)
+            } else {
+                // 未完了のダウンロードのchunkをFuturesUnorderedで並行実行し、縮小し終わったタイルと合わせて最初に返ってきたものを取得する
+                let mut chunks = items
                     .iter_mut()
                     .enumerate()
                     .filter(|(_, item)| !item.is_finished)
                     .map(|(i, item)| item.response.chunk().map(move |result| (i, result)))
-                    .collect::<FuturesUnordered<_>>()
-                    .next()
-                    .await
-            };
-
-            let Some((i, result)) = first_result else {
-                // Noneということは元々0要素だったということ　つまりすべて完了済み
-                log::info!("Download finished");
-                return (
-                    (timestamp, Progress::Finished(items.map(|item| item.data))),
-                    State::Finished,
-                );
+                    .collect::<FuturesUnordered<_>>();
+                tokio::select! {
+                    Some((i, result)) = chunks.next() => Event::Chunk(i, result),
+                    tile = pipeline.next() => Event::Tile(tile),
+                }
             };
 
-            match result {
-                Ok(Some(chunk)) => {
+            match event {
+                Event::Chunk(i, Ok(Some(chunk))) => {
                     items[i].downloaded += chunk.len() as u64;
                     items[i].data.extend(chunk);
                 }
-                Ok(None) => {
+                Event::Chunk(i, Ok(None)) => {
                     items[i].is_finished = true;
+                    let (x, y) = TILE_POSITIONS[i];
+                    pipeline.push(x, y, mem::take(&mut items[i].data));
                 }
-                Err(e) => {
+                Event::Chunk(_, Err(e)) => {
                     return (
                         (timestamp, Progress::Failed(Arc::new(e.into()))),
                         State::Finished,
                     );
                 }
+                Event::Tile(Some(Ok((x, y, tile)))) => {
+                    return (
+                        (timestamp, Progress::TileFinished { x, y, tile }),
+                        State::Downloading {
+                            items,
+                            pipeline,
+                            started_at,
+                        },
+                    );
+                }
+                Event::Tile(Some(Err(e))) => {
+                    return ((timestamp, Progress::Failed(Arc::new(e))), State::Finished);
+                }
+                Event::Tile(None) => {
+                    log::info!("Download finished");
+                    let (image, sources) = match pipeline.finish().await {
+                        Ok(finished) => finished,
+                        Err(e) => {
+                            return ((timestamp, Progress::Failed(Arc::new(e))), State::Finished);
+                        }
+                    };
+                    let tiles = Tiles {
+                        image,
+                        sources,
+                        urls: items.each_ref().map(|item| item.url.clone()),
+                        level: LEVEL,
+                        duration: started_at.elapsed(),
+                    };
+                    return (
+                        (timestamp, Progress::Finished(Box::new(tiles))),
+                        State::Finished,
+                    );
+                }
             }
 
             let downloaded: u64 = items.iter().map(|item| item.downloaded).sum();
             let total: u64 = items.iter().map(|item| item.total).sum();
             let percentage = downloaded as f32 / total as f32;
 
-            // log::info!(
-            //     "{} {} {} {} {percentage}",
-            //     items[0].is_finished,
-            //     items[1].is_finished,
-            //     items[2].is_finished,
-            //     items[3].is_finished
-            // );
             (
                 (timestamp, Progress::Advanced(percentage)),
-                State::Downloading(items),
+                State::Downloading {
+                    items,
+                    pipeline,
+                    started_at,
+                },
             )
         }
         State::Finished => {
@@ -94,12 +185,17 @@ async fn download(timestamp: DownloadId, state: State) -> ((DownloadId, Progress
 
 enum State {
     Ready(DownloadId),
-    Downloading([DownloadItem; 4]),
+    Downloading {
+        items: Box<[DownloadItem; 4]>,
+        pipeline: Box<TilePipeline>,
+        started_at: Instant,
+    },
     Finished,
 }
 
 #[derive(Debug)]
 struct DownloadItem {
+    url: String,
     response: Response,
     total: u64,
     downloaded: u64,
@@ -109,19 +205,14 @@ struct DownloadItem {
 
 async fn get_download_items(id: &DownloadId) -> anyhow::Result<[DownloadItem; 4]> {
     let client = Client::new();
-    let path = id.as_utc_datetime().format("/%Y/%m/%d/%H%M%S").to_string();
-    let urls = [
-        format!("{IMAGE_BASE_URL}{path}_0_0.png"),
-        format!("{IMAGE_BASE_URL}{path}_0_1.png"),
-        format!("{IMAGE_BASE_URL}{path}_1_0.png"),
-        format!("{IMAGE_BASE_URL}{path}_1_1.png"),
-    ];
+    let urls = TILE_POSITIONS.map(|(x, y)| id.tile_url(LEVEL, x, y));
     let futures = urls.map(|u| client.get(u).send());
     let responses = try_join_all(futures).await?;
     let items = responses
         .into_iter()
         .map(|response| {
             response.content_length().map(|total| DownloadItem {
+                url: response.url().to_string(),
                 response,
                 total,
                 downloaded: 0,
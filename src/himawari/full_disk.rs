@@ -0,0 +1,62 @@
+use anyhow::{bail, Context as _};
+use futures::{stream, StreamExt, TryStreamExt};
+use image::{imageops, RgbImage};
+use reqwest::Client;
+
+use super::{DownloadId, TILE_SIZE};
+
+/// 配信されているズームレベル。レベル`n`の全球画像は`n`x`n`枚のタイルからなる
+pub const LEVELS: [u32; 6] = [1, 2, 4, 8, 16, 20];
+
+/// 同時にダウンロードするタイルの数
+const CONCURRENCY: usize = 8;
+
+/// ズームレベル`level`のタイルをすべてダウンロードし、一枚の全球画像につなぎ合わせる
+pub async fn fetch_full_disk(id: DownloadId, level: u32) -> anyhow::Result<RgbImage> {
+    if !LEVELS.contains(&level) {
+        bail!("unsupported level: {level} (available: {LEVELS:?})");
+    }
+
+    let positions = (0..level)
+        .flat_map(|x| (0..level).map(move |y| (x, y)))
+        .collect();
+    let tiles = fetch_tiles(id, level, positions).await?;
+
+    log::info!("Combine {} tiles", tiles.len());
+    let mut combined = RgbImage::new(TILE_SIZE * level, TILE_SIZE * level);
+    for (x, y, tile) in tiles {
+        let (x, y) = ((TILE_SIZE * x) as i64, (TILE_SIZE * y) as i64);
+        imageops::replace(&mut combined, &tile, x, y);
+    }
+    Ok(combined)
+}
+
+/// ズームレベル`level`のタイルのうち、`positions`で指定した位置`(x, y)`のものだけをダウンロードする
+pub async fn fetch_tiles(
+    id: DownloadId,
+    level: u32,
+    positions: Vec<(u32, u32)>,
+) -> anyhow::Result<Vec<(u32, u32, RgbImage)>> {
+    let client = Client::new();
+    let tiles = positions.into_iter().map(|(x, y)| {
+        let client = &client;
+        async move {
+            let url = id.tile_url(level, x, y);
+            let bytes = client
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            let tile = image::load_from_memory(&bytes)
+                .with_context(|| format!("failed to decode {url}"))?
+                .to_rgb8();
+            anyhow::Ok((x, y, tile))
+        }
+    });
+    stream::iter(tiles)
+        .buffer_unordered(CONCURRENCY)
+        .try_collect()
+        .await
+}
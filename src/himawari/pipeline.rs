@@ -0,0 +1,132 @@
+use std::{sync::mpsc, thread};
+
+use iced::widget::image::Handle;
+use image::{imageops, RgbImage};
+use tokio::sync::mpsc as async_mpsc;
+
+use crate::{config::Config, framing, processing::ResizeFilter};
+
+use super::FullDisk;
+
+/// ダウンロードしたタイルを縮小する大きさ
+#[derive(Debug, Clone, Copy)]
+pub struct Resize {
+    /// 縮小したタイルの一辺 [px]
+    pub size: u32,
+    pub filter: ResizeFilter,
+}
+
+impl Resize {
+    /// `output`の大きさで保存する全球画像に合わせる
+    pub fn for_config(config: &Config) -> Self {
+        let output = &config.output;
+        Self {
+            size: framing::disk_size(output.fit, output.width, output.height).div_ceil(2),
+            filter: config.processing.resize_filter,
+        }
+    }
+}
+
+/// 縮小し終わったタイル。`x`, `y`はタイルの位置
+///
+/// 保存した画像に重ねて表示できるように、地球の外側を透明にしたRGBAの画像にしてある。
+pub type ResizedTile = (u32, u32, Handle);
+
+/// 展開したままの大きさのタイル。`x`, `y`はタイルの位置
+pub type SourceTile = (u32, u32, RgbImage);
+
+/// ダウンロードし終わったタイルから順に展開・縮小して、1枚の画像につなぎ合わせる
+///
+/// 展開と縮小、表示用の画像の作成は専用のスレッドで1枚ずつ行うので、残りのタイルのダウンロードと並行して進む。
+/// スレッドは1回のダウンロードごとに作る。
+/// ダウンロードしたデータは展開したらすぐに捨てるが、展開したタイルは領域の切り出しに使うので残しておく。
+pub struct TilePipeline {
+    input: Option<mpsc::Sender<(u32, u32, Vec<u8>)>>,
+    output: async_mpsc::UnboundedReceiver<anyhow::Result<ResizedTile>>,
+    worker: thread::JoinHandle<(RgbImage, Vec<SourceTile>)>,
+}
+
+impl TilePipeline {
+    /// 全球画像を`columns`x`rows`枚に分けたタイルをつなぎ合わせる
+    pub fn new(resize: Resize, columns: u32, rows: u32) -> Self {
+        let (input, tiles) = mpsc::channel::<(u32, u32, Vec<u8>)>();
+        let (results, output) = async_mpsc::unbounded_channel();
+        let worker = thread::spawn(move || {
+            let size = resize.size;
+            let mut combined = RgbImage::new(size * columns, size * rows);
+            let disk = FullDisk::new(size * columns);
+            let mut sources = vec![];
+            for (x, y, data) in tiles {
+                let result = decode(&data).map(|decoded| {
+                    let tile = imageops::resize(&decoded, size, size, resize.filter.into());
+                    sources.push((x, y, decoded));
+                    tile
+                });
+                drop(data);
+                let result = result.map(|tile| {
+                    let (left, top) = (size * x, size * y);
+                    imageops::replace(&mut combined, &tile, left as i64, top as i64);
+                    let masked = disk.mask_space(&tile, left, top);
+                    let handle =
+                        Handle::from_pixels(masked.width(), masked.height(), masked.into_raw());
+                    (x, y, handle)
+                });
+                if results.send(result).is_err() {
+                    break;
+                }
+            }
+            (combined, sources)
+        });
+        Self {
+            input: Some(input),
+            output,
+            worker,
+        }
+    }
+
+    /// ダウンロードし終わったタイルを渡す
+    pub fn push(&self, x: u32, y: u32, data: Vec<u8>) {
+        if let Some(input) = &self.input {
+            // 送れないのはスレッドが止まったときだけで、その場合は`finish`で分かる
+            let _ = input.send((x, y, data));
+        }
+    }
+
+    /// もうタイルを渡さない
+    pub fn close(&mut self) {
+        self.input = None;
+    }
+
+    /// 次に縮小し終わったタイル。`close`したあと、すべて返し終わったら`None`
+    pub async fn next(&mut self) -> Option<anyhow::Result<ResizedTile>> {
+        self.output.recv().await
+    }
+
+    /// つなぎ合わせた画像と、展開したままの大きさのタイルを受け取る
+    pub async fn finish(mut self) -> anyhow::Result<(RgbImage, Vec<SourceTile>)> {
+        self.close();
+        let worker = self.worker;
+        tokio::task::spawn_blocking(move || {
+            worker
+                .join()
+                .map_err(|_| anyhow::anyhow!("tile pipeline panicked"))
+        })
+        .await?
+    }
+}
+
+/// PNGをRGBで展開する
+fn decode(data: &[u8]) -> anyhow::Result<RgbImage> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    if info.color_type == png::ColorType::Rgb {
+        buffer.truncate(info.buffer_size());
+        // 大きさは確かめてあるので失敗しない
+        return Ok(RgbImage::from_raw(info.width, info.height, buffer).unwrap());
+    }
+    // ひまわりのタイルはRGBなので、それ以外の形式は変換する
+    Ok(image::load_from_memory(data)?.to_rgb8())
+}
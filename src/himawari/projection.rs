@@ -0,0 +1,165 @@
+//! ひまわりの全球画像(静止衛星投影)と緯度経度の相互変換
+//!
+//! CGMS LRIT/HRIT Global Specification の変換式に従う。
+//! PROJでいうと `+proj=geos +h=35785863 +a=6378137 +b=6356752.31414 +lon_0=140.7 +sweep=y` に相当する。
+
+use image::{RgbImage, Rgba, RgbaImage};
+
+/// 地球の赤道半径 [m]
+pub const EQUATORIAL_RADIUS: f64 = 6_378_137.0;
+/// 地球の極半径 [m]
+pub const POLAR_RADIUS: f64 = 6_356_752.314_14;
+/// 衛星の地表からの高度 [m]
+pub const SATELLITE_HEIGHT: f64 = 35_785_863.0;
+/// ひまわり9号の直下点経度 [deg]
+pub const SUB_LONGITUDE: f64 = 140.7;
+/// 全球画像の中心から端までの距離(投影座標) [m]
+///
+/// 2km解像度の5500x5500画像がちょうどこの範囲を覆う。
+pub const FULL_DISK_EXTENT: f64 = 5_500_000.0;
+
+/// 地球中心から衛星までの距離 [m]
+const SATELLITE_DISTANCE: f64 = SATELLITE_HEIGHT + EQUATORIAL_RADIUS;
+
+/// 全球画像の画素座標と緯度経度の変換を行う
+#[derive(Debug, Clone, Copy)]
+pub struct FullDisk {
+    size: f64,
+}
+
+impl FullDisk {
+    /// 一辺`size`ピクセルの全球画像に対する変換を返す
+    pub fn new(size: u32) -> Self {
+        Self { size: size as f64 }
+    }
+
+    /// 1ピクセルあたりの走査角 [rad]
+    fn step(&self) -> f64 {
+        2.0 * FULL_DISK_EXTENT / SATELLITE_HEIGHT / self.size
+    }
+
+    /// 地球の縁(赤道方向)までの画像の中心からの距離 [px]
+    pub fn limb_radius(&self) -> f64 {
+        (EQUATORIAL_RADIUS / SATELLITE_DISTANCE).asin() / self.step()
+    }
+
+    /// 左上が`(left, top)`にある全球画像の一部`tile`の、地球の外側を透明にする
+    ///
+    /// 縁は画素にかかる割合で半透明にする。
+    pub fn mask_space(&self, tile: &RgbImage, left: u32, top: u32) -> RgbaImage {
+        let center = self.size / 2.0;
+        let radius = self.limb_radius();
+        RgbaImage::from_fn(tile.width(), tile.height(), |x, y| {
+            let [r, g, b] = tile.get_pixel(x, y).0;
+            let dx = (left + x) as f64 + 0.5 - center;
+            let dy = (top + y) as f64 + 0.5 - center;
+            let alpha = (radius + 0.5 - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0);
+            Rgba([r, g, b, (alpha * 255.0).round() as u8])
+        })
+    }
+
+    /// 緯度経度 [deg] を画素座標に変換する。衛星から見えない地点では`None`を返す
+    pub fn lonlat_to_pixel(&self, lon: f64, lat: f64) -> Option<(f64, f64)> {
+        let (x, y) = lonlat_to_scan_angle(lon, lat)?;
+        let step = self.step();
+        Some((x / step + self.size / 2.0, y / step + self.size / 2.0))
+    }
+
+    /// 画素座標を緯度経度 [deg] に変換する。地球の外では`None`を返す
+    pub fn pixel_to_lonlat(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let step = self.step();
+        scan_angle_to_lonlat((x - self.size / 2.0) * step, (y - self.size / 2.0) * step)
+    }
+}
+
+/// 緯度経度 [deg] を走査角 [rad] に変換する
+///
+/// xは東向き、yは南向きが正。
+fn lonlat_to_scan_angle(lon: f64, lat: f64) -> Option<(f64, f64)> {
+    let a2 = EQUATORIAL_RADIUS * EQUATORIAL_RADIUS;
+    let b2 = POLAR_RADIUS * POLAR_RADIUS;
+    let e2 = (a2 - b2) / a2;
+
+    let lat = lat.to_radians();
+    let dlon = (lon - SUB_LONGITUDE).to_radians();
+    let c_lat = (b2 / a2 * lat.tan()).atan();
+    let rl = POLAR_RADIUS / (1.0 - e2 * c_lat.cos().powi(2)).sqrt();
+
+    let px = rl * c_lat.cos() * dlon.cos();
+    let py = rl * c_lat.cos() * dlon.sin();
+    let pz = rl * c_lat.sin();
+    let r1 = SATELLITE_DISTANCE - px;
+
+    // 地表の法線と衛星方向の内積が負なら地球の裏側
+    if r1 * px - py * py - pz * pz * a2 / b2 < 0.0 {
+        return None;
+    }
+
+    let rn = (r1 * r1 + py * py + pz * pz).sqrt();
+    Some(((py / r1).atan(), (-pz / rn).asin()))
+}
+
+/// 走査角 [rad] を緯度経度 [deg] に変換する。`lonlat_to_scan_angle`の逆
+fn scan_angle_to_lonlat(x: f64, y: f64) -> Option<(f64, f64)> {
+    let a2 = EQUATORIAL_RADIUS * EQUATORIAL_RADIUS;
+    let b2 = POLAR_RADIUS * POLAR_RADIUS;
+    let h = SATELLITE_DISTANCE;
+
+    // 衛星からの視線と地球楕円体の交点のうち近いほう
+    let (cos_x, cos_y) = (x.cos(), y.cos());
+    let k = cos_y * cos_y + a2 / b2 * y.sin().powi(2);
+    let discriminant = (h * cos_x * cos_y).powi(2) - k * (h * h - a2);
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sn = (h * cos_x * cos_y - discriminant.sqrt()) / k;
+
+    let px = h - sn * cos_x * cos_y;
+    let py = sn * x.sin() * cos_y;
+    let pz = -sn * y.sin();
+    let lon = SUB_LONGITUDE + py.atan2(px).to_degrees();
+    let lat = (a2 / b2 * pz / px.hypot(py)).atan().to_degrees();
+    Some((lon, lat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 5500;
+
+    #[test]
+    fn round_trip_through_pixels() {
+        let disk = FullDisk::new(SIZE);
+        for (lon, lat) in [(140.7, 0.0), (139.7, 35.7), (100.0, -30.0), (170.0, 50.0)] {
+            let (x, y) = disk.lonlat_to_pixel(lon, lat).unwrap();
+            let (lon2, lat2) = disk.pixel_to_lonlat(x, y).unwrap();
+            assert!((lon - lon2).abs() < 1e-6, "{lon} -> {lon2}");
+            assert!((lat - lat2).abs() < 1e-6, "{lat} -> {lat2}");
+        }
+    }
+
+    #[test]
+    fn sub_satellite_point_is_centre() {
+        let disk = FullDisk::new(SIZE);
+        let (x, y) = disk.lonlat_to_pixel(SUB_LONGITUDE, 0.0).unwrap();
+        let center = SIZE as f64 / 2.0;
+        assert!((x - center).abs() < 1e-6 && (y - center).abs() < 1e-6);
+
+        // 北東の地点は右上に写る
+        let (x, y) = disk.lonlat_to_pixel(139.7 + 10.0, 35.7).unwrap();
+        assert!(x > center && y < center);
+    }
+
+    #[test]
+    fn beyond_limb_is_none() {
+        let disk = FullDisk::new(SIZE);
+        assert!(disk.lonlat_to_pixel(SUB_LONGITUDE + 100.0, 0.0).is_none());
+        assert!(disk.lonlat_to_pixel(SUB_LONGITUDE - 90.0, 0.0).is_none());
+        assert!(disk.lonlat_to_pixel(SUB_LONGITUDE, 85.0).is_none());
+        // 画像の隅は宇宙
+        assert!(disk.pixel_to_lonlat(0.0, 0.0).is_none());
+        let limb = SIZE as f64 / 2.0 + disk.limb_radius() + 1.0;
+        assert!(disk.pixel_to_lonlat(limb, SIZE as f64 / 2.0).is_none());
+    }
+}
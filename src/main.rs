@@ -1,10 +1,32 @@
 use app::App;
+use config::Config;
 use iced::{Application, Settings};
 
 mod app;
+#[cfg(feature = "bench")]
+mod bench;
+mod cli;
+mod config;
+mod export;
+mod framing;
 mod himawari;
+mod processing;
+mod quality;
+mod region;
 
-fn main() -> iced::Result {
+#[cfg(feature = "bench")]
+#[global_allocator]
+static ALLOCATOR: bench::CountingAllocator = bench::CountingAllocator;
+
+fn main() -> anyhow::Result<()> {
     env_logger::init();
-    App::run(Settings::default())
+    let config = Config::load()?;
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        return cli::run(&config, &args);
+    }
+
+    App::run(Settings::with_flags(config))?;
+    Ok(())
 }
@@ -0,0 +1,342 @@
+use image::{imageops, GrayImage, Luma, RgbImage};
+use rayon::{
+    prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+use serde::{Deserialize, Serialize};
+
+/// 保存前に画像に順番に適用する処理
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Stage {
+    /// ガンマ補正。1より大きいと明るくなる
+    Gamma { gamma: f32 },
+    /// 明るさの下位`low`%と上位`high`%がそれぞれ黒と白になるように引き伸ばす
+    AutoLevels {
+        #[serde(default = "default_low_percentile")]
+        low: f32,
+        #[serde(default = "default_high_percentile")]
+        high: f32,
+    },
+    /// 彩度を`factor`倍にする
+    Saturation { factor: f32 },
+    /// アンシャープマスク
+    UnsharpMask {
+        sigma: f32,
+        #[serde(default)]
+        threshold: i32,
+    },
+    /// ダークチャネルプライアによる霞の除去。`strength`は0から1
+    HazeRemoval { strength: f32 },
+}
+
+fn default_low_percentile() -> f32 {
+    0.5
+}
+
+fn default_high_percentile() -> f32 {
+    99.5
+}
+
+impl Stage {
+    /// 処理できる値か確かめる
+    pub fn check(&self) -> anyhow::Result<()> {
+        let is_positive = |value: f32| value.is_finite() && value > 0.0;
+        match *self {
+            Stage::Gamma { gamma } if !is_positive(gamma) => {
+                anyhow::bail!("gamma must be a positive number: {gamma}")
+            }
+            Stage::AutoLevels { low, high } if !(0.0 <= low && low < high && high <= 100.0) => {
+                anyhow::bail!("percentiles must satisfy 0 <= low < high <= 100: {low}, {high}")
+            }
+            Stage::Saturation { factor } if !(factor.is_finite() && factor >= 0.0) => {
+                anyhow::bail!("factor must not be negative: {factor}")
+            }
+            Stage::UnsharpMask { sigma, .. } if !is_positive(sigma) => {
+                anyhow::bail!("sigma must be a positive number: {sigma}")
+            }
+            Stage::HazeRemoval { strength } if !(0.0..=1.0).contains(&strength) => {
+                anyhow::bail!("strength must be between 0 and 1: {strength}")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, image: &mut RgbImage) {
+        match *self {
+            Stage::Gamma { gamma } => apply_gamma(image, gamma),
+            Stage::AutoLevels { low, high } => apply_auto_levels(image, low, high),
+            Stage::Saturation { factor } => apply_saturation(image, factor),
+            Stage::UnsharpMask { sigma, threshold } => {
+                *image = imageops::unsharpen(image, sigma, threshold);
+            }
+            Stage::HazeRemoval { strength } => apply_haze_removal(image, strength),
+        }
+    }
+}
+
+/// リサイズに使うフィルタ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for imageops::FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => imageops::FilterType::Nearest,
+            ResizeFilter::Triangle => imageops::FilterType::Triangle,
+            ResizeFilter::CatmullRom => imageops::FilterType::CatmullRom,
+            ResizeFilter::Gaussian => imageops::FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+/// 各画素の各チャンネルを変換表で置き換える
+fn apply_lut(image: &mut RgbImage, lut: &[u8; 256]) {
+    image
+        .par_chunks_mut(4096)
+        .for_each(|chunk| chunk.iter_mut().for_each(|c| *c = lut[*c as usize]));
+}
+
+fn apply_gamma(image: &mut RgbImage, gamma: f32) {
+    let lut = std::array::from_fn(|i| {
+        let value = (i as f32 / 255.0).powf(1.0 / gamma);
+        (value * 255.0).round() as u8
+    });
+    apply_lut(image, &lut);
+}
+
+fn apply_auto_levels(image: &mut RgbImage, low: f32, high: f32) {
+    // 宇宙の黒い画素は数えない
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        let luma = luma(pixel.0);
+        if luma > 0 {
+            histogram[luma as usize] += 1;
+        }
+    }
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return;
+    }
+
+    let percentile = |p: f32| {
+        let target = (total as f64 * p as f64 / 100.0) as u64;
+        let mut count = 0;
+        histogram
+            .iter()
+            .position(|n| {
+                count += n;
+                count > target
+            })
+            .unwrap_or(255) as f32
+    };
+    let (black, white) = (percentile(low), percentile(high));
+    if white <= black {
+        return;
+    }
+
+    let lut = std::array::from_fn(|i| {
+        let value = (i as f32 - black) / (white - black);
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    });
+    apply_lut(image, &lut);
+}
+
+fn apply_saturation(image: &mut RgbImage, factor: f32) {
+    image.par_chunks_mut(3).for_each(|pixel| {
+        let luma = luma([pixel[0], pixel[1], pixel[2]]) as f32;
+        for c in pixel {
+            *c = (luma + (*c as f32 - luma) * factor)
+                .clamp(0.0, 255.0)
+                .round() as u8;
+        }
+    });
+}
+
+fn apply_haze_removal(image: &mut RgbImage, strength: f32) {
+    if image.width() == 0 || image.height() == 0 {
+        return;
+    }
+    // 各画素のチャンネルの最小値(ダークチャネル)をぼかして霞の濃さとみなす
+    let dark = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([*image.get_pixel(x, y).0.iter().min().unwrap()])
+    });
+    let dark = imageops::blur(&dark, 4.0);
+
+    // 大気光はダークチャネルの上位0.1%の明るさとする
+    let mut values = dark.as_raw().clone();
+    let index = values.len() * 999 / 1000;
+    let airlight = *values.select_nth_unstable(index).1 as f32;
+    if airlight == 0.0 {
+        return;
+    }
+
+    let strength = strength.clamp(0.0, 1.0);
+    image
+        .par_chunks_mut(3)
+        .zip(dark.as_raw().par_iter())
+        .for_each(|(pixel, &dark)| {
+            let transmission = (1.0 - strength * dark as f32 / airlight).max(0.1);
+            for c in pixel {
+                let value = (*c as f32 - airlight) / transmission + airlight;
+                *c = value.clamp(0.0, 255.0).round() as u8;
+            }
+        });
+}
+
+/// ITU-R BT.601の輝度
+pub fn luma([r, g, b]: [u8; 3]) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    /// 左から右に明るさが`from`から`to`まで変わる画像
+    fn gradient(from: u8, to: u8) -> RgbImage {
+        RgbImage::from_fn(64, 4, |x, _| {
+            let value = from as u32 + (to - from) as u32 * x / 63;
+            Rgb([value as u8; 3])
+        })
+    }
+
+    fn channel_range(image: &RgbImage) -> (u8, u8) {
+        let values = image.as_raw();
+        (*values.iter().min().unwrap(), *values.iter().max().unwrap())
+    }
+
+    #[test]
+    fn gamma_brightens_midtones() {
+        let mut image = RgbImage::from_pixel(2, 2, Rgb([0, 128, 255]));
+        Stage::Gamma { gamma: 2.0 }.apply(&mut image);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 181, 255]);
+
+        let mut image = gradient(0, 255);
+        let original = image.clone();
+        Stage::Gamma { gamma: 1.0 }.apply(&mut image);
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn auto_levels_stretches_to_full_range() {
+        let mut image = gradient(50, 150);
+        Stage::AutoLevels {
+            low: 1.0,
+            high: 99.0,
+        }
+        .apply(&mut image);
+        assert_eq!(channel_range(&image), (0, 255));
+    }
+
+    #[test]
+    fn auto_levels_ignores_black_image() {
+        let mut image = RgbImage::new(8, 8);
+        Stage::AutoLevels {
+            low: 0.5,
+            high: 99.5,
+        }
+        .apply(&mut image);
+        assert_eq!(channel_range(&image), (0, 0));
+    }
+
+    #[test]
+    fn saturation_zero_makes_gray() {
+        let mut image = RgbImage::from_pixel(2, 2, Rgb([200, 100, 50]));
+        Stage::Saturation { factor: 0.0 }.apply(&mut image);
+        let [r, g, b] = image.get_pixel(0, 0).0;
+        assert_eq!((r, g), (g, b));
+        assert_eq!(r, luma([200, 100, 50]));
+    }
+
+    #[test]
+    fn unsharp_mask_keeps_flat_image() {
+        let mut image = RgbImage::from_pixel(16, 16, Rgb([90, 120, 150]));
+        let original = image.clone();
+        Stage::UnsharpMask {
+            sigma: 1.0,
+            threshold: 0,
+        }
+        .apply(&mut image);
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn haze_removal_increases_contrast() {
+        let mut image = RgbImage::from_fn(32, 32, |x, _| {
+            if x < 16 {
+                Rgb([120, 130, 140])
+            } else {
+                Rgb([200, 200, 210])
+            }
+        });
+        let original = image.clone();
+        Stage::HazeRemoval { strength: 0.0 }.apply(&mut image);
+        assert_eq!(image, original);
+
+        Stage::HazeRemoval { strength: 0.8 }.apply(&mut image);
+        let (min, _) = channel_range(&image);
+        assert!(min < channel_range(&original).0);
+    }
+
+    #[test]
+    fn stages_accept_empty_image() {
+        let stages = [
+            Stage::Gamma { gamma: 2.2 },
+            Stage::AutoLevels {
+                low: 0.5,
+                high: 99.5,
+            },
+            Stage::Saturation { factor: 1.5 },
+            Stage::UnsharpMask {
+                sigma: 1.0,
+                threshold: 0,
+            },
+            Stage::HazeRemoval { strength: 0.5 },
+        ];
+        for stage in stages {
+            stage.check().unwrap();
+            let mut image = RgbImage::new(0, 0);
+            stage.apply(&mut image);
+            assert!(image.is_empty());
+        }
+    }
+
+    #[test]
+    fn check_rejects_invalid_parameters() {
+        let stages = [
+            Stage::Gamma { gamma: 0.0 },
+            Stage::Gamma { gamma: -1.0 },
+            Stage::Gamma { gamma: f32::NAN },
+            Stage::AutoLevels {
+                low: 60.0,
+                high: 40.0,
+            },
+            Stage::AutoLevels {
+                low: -1.0,
+                high: 99.5,
+            },
+            Stage::Saturation { factor: -0.5 },
+            Stage::UnsharpMask {
+                sigma: 0.0,
+                threshold: 0,
+            },
+            Stage::HazeRemoval { strength: 1.5 },
+            Stage::HazeRemoval { strength: f32::NAN },
+        ];
+        for stage in stages {
+            assert!(stage.check().is_err(), "{stage:?}");
+        }
+    }
+}
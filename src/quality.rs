@@ -0,0 +1,293 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+use crate::{himawari::FullDisk, processing::luma};
+
+/// 縦横に調べる点の数
+const GRID_SIZE: u32 = 64;
+/// これより暗い画素は欠けているとみなす
+const BLACK_LUMA: u8 = 6;
+/// 太陽の天頂角の余弦がこれより大きい地点を昼とみなす(高度およそ10度以上)
+const DAYLIGHT_COS_ZENITH: f64 = 0.17;
+/// 隣の行との平均の明るさの差がこれより大きい行を走査線の乱れとみなす
+const STRIPE_LUMA: f64 = 8.0;
+
+/// 画像の品質の評価
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Grade {
+    #[default]
+    Good,
+    /// 表示はするが怪しい
+    Suspect,
+    /// 表示しない
+    Bad,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assessment {
+    pub grade: Grade,
+    /// 評価を下げた理由
+    pub reasons: Vec<String>,
+}
+
+impl Assessment {
+    fn report(&mut self, grade: Grade, reason: impl Into<String>) {
+        self.grade = self.grade.max(grade);
+        self.reasons.push(reason.into());
+    }
+}
+
+/// つなぎ合わせた直後の全球画像の品質を調べる
+///
+/// 夜の地域は暗くて当然なので、黒い部分の判定は撮影時刻に昼だったはずの地点だけで行う。
+/// 食の時期かどうかでは評価を下げず、ほかの判定で評価が下がったときに考えられる理由として添えるだけにする。
+pub fn assess(disk: &RgbImage, captured_at: DateTime<Utc>) -> Assessment {
+    let mut assessment = Assessment::default();
+    check_coverage(disk, captured_at, &mut assessment);
+    check_stripes(disk, &mut assessment);
+    check_stray_light(disk, &mut assessment);
+    if assessment.grade > Grade::Good && is_eclipse_season(captured_at) {
+        assessment.reasons.push("eclipse season".to_string());
+    }
+    assessment
+}
+
+/// 昼の地点が黒くなっていないか、全体と4つのタイルごとに調べる
+fn check_coverage(disk: &RgbImage, captured_at: DateTime<Utc>, assessment: &mut Assessment) {
+    let size = disk.width();
+    let full_disk = FullDisk::new(size);
+    let sun = SunPosition::new(captured_at);
+
+    // [昼の点の数, そのうち黒い点の数]をタイルごとに数える
+    let mut counts = [[0u32; 2]; 4];
+    for j in 0..GRID_SIZE {
+        for i in 0..GRID_SIZE {
+            let x = (i as f64 + 0.5) * size as f64 / GRID_SIZE as f64;
+            let y = (j as f64 + 0.5) * size as f64 / GRID_SIZE as f64;
+            let Some((lon, lat)) = full_disk.pixel_to_lonlat(x, y) else {
+                continue;
+            };
+            if sun.cos_zenith(lon, lat) < DAYLIGHT_COS_ZENITH {
+                continue;
+            }
+            let tile = (i * 2 / GRID_SIZE * 2 + j * 2 / GRID_SIZE) as usize;
+            counts[tile][0] += 1;
+            if luma(disk.get_pixel(x as u32, y as u32).0) < BLACK_LUMA {
+                counts[tile][1] += 1;
+            }
+        }
+    }
+
+    let (daylit, black) = counts
+        .iter()
+        .fold((0, 0), |(daylit, black), [d, b]| (daylit + d, black + b));
+    // 真夜中など昼の地点がほとんどない場合は判定しない
+    if daylit < GRID_SIZE * GRID_SIZE / 20 {
+        return;
+    }
+    let black_ratio = black as f64 / daylit as f64;
+    if black_ratio > 0.5 {
+        assessment.report(Grade::Bad, "mostly black");
+        return;
+    }
+
+    const TILE_NAMES: [&str; 4] = ["NW", "SW", "NE", "SE"];
+    let mut has_missing_tile = false;
+    for ([daylit, black], name) in counts.iter().zip(TILE_NAMES) {
+        if *daylit >= GRID_SIZE && *black as f64 / *daylit as f64 > 0.9 {
+            assessment.report(Grade::Bad, format!("missing {name} quadrant"));
+            has_missing_tile = true;
+        }
+    }
+    if !has_missing_tile && black_ratio > 0.1 {
+        assessment.report(Grade::Suspect, "partly black");
+    }
+}
+
+/// 行ごとの平均の明るさが前後の行から飛び離れた行(走査線の乱れ)を数える
+fn check_stripes(disk: &RgbImage, assessment: &mut Assessment) {
+    let size = disk.width();
+    let center = size as f64 / 2.0;
+    let radius = FullDisk::new(size).limb_radius() * 0.98;
+
+    // 地球を横切る長さが短すぎる行は平均がばらつくので使わない
+    let means = (0..size)
+        .map(|y| {
+            let dy = y as f64 + 0.5 - center;
+            let half_chord = (radius * radius - dy * dy).max(0.0).sqrt();
+            if half_chord < size as f64 * 0.05 {
+                return None;
+            }
+            let (start, end) = ((center - half_chord) as u32, (center + half_chord) as u32);
+            let sum = (start..end)
+                .map(|x| luma(disk.get_pixel(x, y).0) as u64)
+                .sum::<u64>();
+            Some(sum as f64 / (end - start) as f64)
+        })
+        .collect::<Vec<_>>();
+
+    let (mut rows, mut stripes) = (0, 0);
+    for window in means.windows(3) {
+        let [Some(above), Some(row), Some(below)] = *window else {
+            continue;
+        };
+        rows += 1;
+        if (row - (above + below) / 2.0).abs() > STRIPE_LUMA {
+            stripes += 1;
+        }
+    }
+    if rows == 0 {
+        return;
+    }
+    let ratio = stripes as f64 / rows as f64;
+    if ratio > 0.02 {
+        assessment.report(Grade::Bad, "scan-line artefacts");
+    } else if ratio > 0.005 {
+        assessment.report(Grade::Suspect, "scan-line artefacts");
+    }
+}
+
+/// 宇宙の部分が明るくなっていないか(太陽光の迷光)調べる
+fn check_stray_light(disk: &RgbImage, assessment: &mut Assessment) {
+    let size = disk.width();
+    let center = size as f64 / 2.0;
+    let radius = FullDisk::new(size).limb_radius() * 1.02;
+
+    let (mut space, mut bright) = (0, 0);
+    for j in 0..GRID_SIZE {
+        for i in 0..GRID_SIZE {
+            let x = (i as f64 + 0.5) * size as f64 / GRID_SIZE as f64;
+            let y = (j as f64 + 0.5) * size as f64 / GRID_SIZE as f64;
+            if (x - center).hypot(y - center) < radius {
+                continue;
+            }
+            space += 1;
+            if luma(disk.get_pixel(x as u32, y as u32).0) > 40 {
+                bright += 1;
+            }
+        }
+    }
+    if space > 0 && bright as f64 / space as f64 > 0.01 {
+        assessment.report(Grade::Suspect, "stray light");
+    }
+}
+
+/// 衛星が地球の影に入る食の時期の真夜中前後か
+///
+/// 春分・秋分の前後3週間ほど、衛星直下の真夜中(14:37 UTCごろ)を中心に最大70分ほど続く。
+fn is_eclipse_season(captured_at: DateTime<Utc>) -> bool {
+    let day = captured_at.ordinal() as i32;
+    let near_equinox = [79, 265].iter().any(|equinox| (day - equinox).abs() <= 22);
+    let minutes = (captured_at.hour() * 60 + captured_at.minute()) as i32;
+    near_equinox && (minutes - (14 * 60 + 37)).abs() <= 40
+}
+
+/// 太陽の位置の簡単な近似
+struct SunPosition {
+    /// 赤緯 [rad]
+    declination: f64,
+    /// 太陽直下点の経度 [deg]
+    longitude: f64,
+}
+
+impl SunPosition {
+    fn new(datetime: DateTime<Utc>) -> Self {
+        let day = datetime.ordinal() as f64;
+        let declination =
+            (-23.44f64).to_radians() * (360.0 / 365.0 * (day + 10.0)).to_radians().cos();
+        // 均時差 [min]
+        let b = (360.0 / 365.0 * (day - 81.0)).to_radians();
+        let equation_of_time = 9.87 * (2.0 * b).sin() - 7.53 * b.cos() - 1.5 * b.sin();
+        let hours = datetime.hour() as f64
+            + datetime.minute() as f64 / 60.0
+            + datetime.second() as f64 / 3600.0;
+        let longitude = -15.0 * (hours - 12.0 + equation_of_time / 60.0);
+        Self {
+            declination,
+            longitude,
+        }
+    }
+
+    /// 地点 [deg] での太陽の天頂角の余弦
+    fn cos_zenith(&self, lon: f64, lat: f64) -> f64 {
+        let lat = lat.to_radians();
+        let hour_angle = (lon - self.longitude).to_radians();
+        lat.sin() * self.declination.sin() + lat.cos() * self.declination.cos() * hour_angle.cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use image::Rgb;
+
+    use super::*;
+
+    const SIZE: u32 = 256;
+
+    /// 衛星直下点がほぼ正午で、食の時期でない時刻
+    fn noon() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 6, 15, 3, 0, 0).unwrap()
+    }
+
+    /// 地球の部分を`luma(x, y)`の明るさの灰色で、宇宙を黒で塗った全球画像
+    fn synthetic_disk(luma: impl Fn(u32, u32) -> u8) -> RgbImage {
+        let center = SIZE as f64 / 2.0;
+        let radius = FullDisk::new(SIZE).limb_radius();
+        RgbImage::from_fn(SIZE, SIZE, |x, y| {
+            let distance = (x as f64 + 0.5 - center).hypot(y as f64 + 0.5 - center);
+            if distance < radius {
+                Rgb([luma(x, y); 3])
+            } else {
+                Rgb([0; 3])
+            }
+        })
+    }
+
+    #[test]
+    fn clean_disk_is_good() {
+        let assessment = assess(&synthetic_disk(|_, _| 120), noon());
+        assert_eq!(assessment, Assessment::default());
+    }
+
+    #[test]
+    fn black_frame_is_bad() {
+        let assessment = assess(&RgbImage::new(SIZE, SIZE), noon());
+        assert_eq!(assessment.grade, Grade::Bad);
+        assert!(assessment.reasons.contains(&"mostly black".to_string()));
+    }
+
+    #[test]
+    fn blank_quadrant_is_flagged() {
+        let half = SIZE / 2;
+        let disk = synthetic_disk(|x, y| if x < half && y < half { 0 } else { 120 });
+        let assessment = assess(&disk, noon());
+        assert!(assessment.grade >= Grade::Suspect);
+        assert!(assessment
+            .reasons
+            .contains(&"missing NW quadrant".to_string()));
+    }
+
+    #[test]
+    fn striped_rows_are_flagged() {
+        let disk = synthetic_disk(|_, y| if y % 2 == 0 { 120 } else { 60 });
+        let assessment = assess(&disk, noon());
+        assert!(assessment.grade >= Grade::Suspect);
+        assert!(assessment
+            .reasons
+            .contains(&"scan-line artefacts".to_string()));
+    }
+
+    #[test]
+    fn eclipse_season_alone_keeps_grade() {
+        // 春分の日の衛星直下の真夜中。昼の地点がないので黒い部分は判定しない
+        let midnight = Utc.with_ymd_and_hms(2023, 3, 20, 14, 37, 0).unwrap();
+        let assessment = assess(&synthetic_disk(|_, _| 120), midnight);
+        assert_eq!(assessment, Assessment::default());
+
+        let assessment = assess(&synthetic_disk(|_, y| (y % 2 * 60) as u8 + 60), midnight);
+        assert!(assessment.reasons.contains(&"eclipse season".to_string()));
+    }
+}
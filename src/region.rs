@@ -0,0 +1,308 @@
+use image::{imageops, DynamicImage, RgbImage};
+use serde::Deserialize;
+
+use crate::{
+    export::Bounds,
+    himawari::{self, DownloadId, FullDisk, Tiles, LEVELS, TILE_SIZE},
+    processing::ResizeFilter,
+};
+
+/// 領域の画像の`product`の接頭辞
+const PRODUCT_PREFIX: &str = "region-";
+/// 最大のズームレベル。`Area::Pixels`の座標はこのレベルの全球画像上で指定する
+const MAX_LEVEL: u32 = LEVELS[LEVELS.len() - 1];
+
+/// 全球画像とは別に切り出して保存する領域
+#[derive(Debug, Clone, Deserialize)]
+pub struct Region {
+    /// 一覧に表示する名前
+    pub name: String,
+    pub area: Area,
+    /// 保存する画像の大きさの上限。縦横比は保つ。省略すると`output`の大きさになる
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+}
+
+/// 切り出す範囲
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Area {
+    /// 最大解像度(レベル20、11000x11000)の全球画像上の矩形 [px]
+    Pixels {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// 緯度経度の範囲を囲む矩形
+    LatLon(Bounds),
+}
+
+impl Area {
+    /// 最大解像度の全球画像上の矩形`(left, top, right, bottom)` [px]。衛星から見えない範囲なら`None`
+    fn pixel_bounds(&self) -> Option<(f64, f64, f64, f64)> {
+        let size = (TILE_SIZE * MAX_LEVEL) as f64;
+        let (left, top, right, bottom) = match *self {
+            Area::Pixels {
+                x,
+                y,
+                width,
+                height,
+            } => (
+                x as f64,
+                y as f64,
+                x as f64 + width as f64,
+                y as f64 + height as f64,
+            ),
+            Area::LatLon(bounds) => {
+                // 投影すると辺が曲がるので、格子状に調べて外接する矩形を求める
+                const SAMPLES: u32 = 32;
+                let projection = FullDisk::new(TILE_SIZE * MAX_LEVEL);
+                let points = (0..=SAMPLES)
+                    .flat_map(|i| (0..=SAMPLES).map(move |j| (i, j)))
+                    .filter_map(|(i, j)| {
+                        let lon = bounds.west + bounds.width() * i as f64 / SAMPLES as f64;
+                        let lat = bounds.south + bounds.height() * j as f64 / SAMPLES as f64;
+                        projection.lonlat_to_pixel(lon, lat)
+                    })
+                    .collect::<Vec<_>>();
+                if points.is_empty() {
+                    return None;
+                }
+                points.iter().fold(
+                    (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+                    |(left, top, right, bottom), &(x, y)| {
+                        (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                    },
+                )
+            }
+        };
+        let (left, top) = (left.clamp(0.0, size), top.clamp(0.0, size));
+        let (right, bottom) = (right.clamp(0.0, size), bottom.clamp(0.0, size));
+        (left < right && top < bottom).then_some((left, top, right, bottom))
+    }
+}
+
+impl Region {
+    /// 切り出せる範囲か確かめる
+    pub fn check(&self) -> anyhow::Result<()> {
+        let size = TILE_SIZE * MAX_LEVEL;
+        if let Area::Pixels {
+            x,
+            y,
+            width,
+            height,
+        } = self.area
+        {
+            if width == 0 || height == 0 {
+                anyhow::bail!("area must not be empty: {width}x{height}");
+            }
+            if x.checked_add(width).is_none_or(|right| right > size)
+                || y.checked_add(height).is_none_or(|bottom| bottom > size)
+            {
+                anyhow::bail!(
+                    "area must be within the {size}x{size} full disk: {width}x{height} at ({x}, {y})"
+                );
+            }
+        }
+        if self.area.pixel_bounds().is_none() {
+            anyhow::bail!("area is not visible from the satellite");
+        }
+        if self.width == Some(0) || self.height == Some(0) {
+            anyhow::bail!("size must not be zero: {:?}x{:?}", self.width, self.height);
+        }
+        Ok(())
+    }
+
+    /// 保存する画像の`product`。名前の英数字以外は`_`にする
+    pub fn product(&self) -> String {
+        let slug = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        format!("{PRODUCT_PREFIX}{slug}")
+    }
+
+    /// 範囲を切り出す。使ったズームレベルと画像を返す
+    ///
+    /// 保存する大きさを下回らない最小のズームレベルを使うが、`downloaded`のタイルで足りればそれを使う。
+    /// 足りないタイルだけをダウンロードし、ダウンロードできなければ解像度が足りなくても`downloaded`から切り出す。
+    pub async fn render(
+        &self,
+        id: DownloadId,
+        downloaded: &Tiles,
+        max_width: u32,
+        max_height: u32,
+        filter: ResizeFilter,
+    ) -> anyhow::Result<(u32, RgbImage)> {
+        let max_width = self.width.unwrap_or(max_width);
+        let max_height = self.height.unwrap_or(max_height);
+        let Some(bounds) = self.area.pixel_bounds() else {
+            anyhow::bail!("{} is not visible from the satellite", self.name);
+        };
+        let (left, top, right, bottom) = bounds;
+        let level = LEVELS
+            .into_iter()
+            .find(|level| {
+                let scale = *level as f64 / MAX_LEVEL as f64;
+                (right - left) * scale >= max_width as f64
+                    || (bottom - top) * scale >= max_height as f64
+            })
+            .unwrap_or(MAX_LEVEL)
+            .max(downloaded.level);
+
+        let (level, cropped) = match crop(id, level, bounds, downloaded).await {
+            Ok(cropped) => (level, cropped),
+            Err(e) if level != downloaded.level => {
+                log::warn!("failed to download tiles for region {}: {e}", self.name);
+                let cropped = crop(id, downloaded.level, bounds, downloaded).await?;
+                (downloaded.level, cropped)
+            }
+            Err(e) => return Err(e),
+        };
+        let resized = DynamicImage::ImageRgb8(cropped)
+            .resize(max_width, max_height, filter.into())
+            .to_rgb8();
+        Ok((level, resized))
+    }
+}
+
+/// 最大解像度の全球画像上の矩形`bounds`を、レベル`level`のタイルから切り出す
+///
+/// `downloaded`と同じレベルなら、そのタイルはダウンロードし直さない。
+async fn crop(
+    id: DownloadId,
+    level: u32,
+    bounds: (f64, f64, f64, f64),
+    downloaded: &Tiles,
+) -> anyhow::Result<RgbImage> {
+    // 選んだレベルの全球画像上の範囲
+    let scale = level as f64 / MAX_LEVEL as f64;
+    let (left, top, right, bottom) = bounds;
+    let (left, top) = ((left * scale) as u32, (top * scale) as u32);
+    let right = ((right * scale).ceil() as u32).max(left + 1);
+    let bottom = ((bottom * scale).ceil() as u32).max(top + 1);
+    let positions = (left / TILE_SIZE..right.div_ceil(TILE_SIZE))
+        .flat_map(|x| (top / TILE_SIZE..bottom.div_ceil(TILE_SIZE)).map(move |y| (x, y)))
+        .collect::<Vec<_>>();
+
+    let reused = downloaded
+        .sources
+        .iter()
+        .filter(|(x, y, _)| level == downloaded.level && positions.contains(&(*x, *y)))
+        .collect::<Vec<_>>();
+    let missing = positions
+        .into_iter()
+        .filter(|&(x, y)| !reused.iter().any(|tile| (tile.0, tile.1) == (x, y)))
+        .collect::<Vec<_>>();
+    let fetched = if missing.is_empty() {
+        vec![]
+    } else {
+        himawari::fetch_tiles(id, level, missing).await?
+    };
+
+    let mut cropped = RgbImage::new(right - left, bottom - top);
+    for (x, y, tile) in reused.into_iter().chain(&fetched) {
+        let x = (TILE_SIZE * x) as i64 - left as i64;
+        let y = (TILE_SIZE * y) as i64 - top as i64;
+        imageops::replace(&mut cropped, tile, x, y);
+    }
+    Ok(cropped)
+}
+
+/// 領域の画像の`product`か
+pub fn is_region_product(product: &str) -> bool {
+    product.starts_with(PRODUCT_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use image::Rgb;
+
+    use super::*;
+
+    /// レベル2の4枚のタイル。タイルごとに違う色で塗る
+    fn downloaded() -> Tiles {
+        let sources = [(0, 0), (0, 1), (1, 0), (1, 1)]
+            .map(|(x, y)| {
+                let color = Rgb([x as u8 * 100, y as u8 * 100, 50]);
+                (x, y, RgbImage::from_pixel(TILE_SIZE, TILE_SIZE, color))
+            })
+            .to_vec();
+        Tiles {
+            image: RgbImage::new(2, 2),
+            sources,
+            level: 2,
+            urls: Default::default(),
+            duration: Duration::ZERO,
+        }
+    }
+
+    fn pixels(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            name: "Test".to_string(),
+            area: Area::Pixels {
+                x,
+                y,
+                width,
+                height,
+            },
+            width: None,
+            height: None,
+        }
+    }
+
+    #[test]
+    fn check_rejects_invalid_areas() {
+        assert!(pixels(4000, 3000, 2000, 2000).check().is_ok());
+        assert!(pixels(0, 0, 11000, 11000).check().is_ok());
+        for region in [
+            pixels(4000, 3000, 0, 2000),
+            pixels(4000, 3000, 2000, 0),
+            pixels(10000, 3000, 2000, 2000),
+            pixels(11000, 0, 1, 1),
+            pixels(1, 1, u32::MAX, 2000),
+            pixels(1, u32::MAX, 2000, 2000),
+        ] {
+            assert!(region.check().is_err(), "{:?}", region.area);
+        }
+        // 地球の裏側
+        let hidden = Region {
+            area: Area::LatLon(Bounds {
+                west: -60.0,
+                east: -30.0,
+                south: 0.0,
+                north: 30.0,
+            }),
+            ..pixels(0, 0, 1, 1)
+        };
+        assert!(hidden.check().is_err());
+    }
+
+    #[tokio::test]
+    async fn renders_from_downloaded_tiles() {
+        // 配信されていない時刻にしておき、ダウンロードしようとしたら失敗させる
+        let id = DownloadId::new(Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap());
+        let half = TILE_SIZE * MAX_LEVEL / 2;
+        let region = pixels(half, half, half, half);
+        let (level, image) = region
+            .render(id, &downloaded(), 100, 100, ResizeFilter::Nearest)
+            .await
+            .unwrap();
+        assert_eq!(level, 2);
+        assert_eq!(image.dimensions(), (100, 100));
+        assert!(image.pixels().all(|pixel| *pixel == Rgb([100, 100, 50])));
+    }
+}